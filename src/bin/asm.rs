@@ -1,24 +1,79 @@
 extern crate myopic;
 
-use myopic::parse_tr_unit;
+use myopic::{
+    assemble, coff, debug_info, elf, hex, lint, listing, map, mpasm,
+    normalize, selects_mpasm,
+};
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::process::exit;
 
+fn usage() -> ! {
+    eprintln!(
+        "Usage: asm [--mpasm] [--hex OUT] [--listing OUT] [--map OUT] \
+            [--debug OUT] [--elf OUT] [--coff OUT] FILE"
+    );
+    exit(2);
+}
+
+fn fail(path: &str, err: &str) -> ! {
+    eprintln!("{}: {}", path, err);
+    exit(1);
+}
+
 fn main() {
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: asm FILE");
-        exit(2);
+    let mut outputs = vec![];
+    let mut in_path = None;
+    let mut is_mpasm = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--hex" | "--listing" | "--map" | "--debug" | "--elf"
+            | "--coff" => {
+                let out_path = args.next().unwrap_or_else(|| usage());
+                outputs.push((arg, out_path));
+            },
+            "--mpasm" => is_mpasm = true,
+            _ if in_path.is_none() && !arg.starts_with("--") => {
+                in_path = Some(arg);
+            },
+            _ => usage(),
+        }
     }
+    let in_path = in_path.unwrap_or_else(|| usage());
 
-    let mut f = File::open(&args[1]).unwrap();
-    let input = {
-        let mut input = String::new();
-        f.read_to_string(&mut input).unwrap();
+    let input = fs::read_to_string(&in_path)
+        .unwrap_or_else(|err| fail(&in_path, &err.to_string()));
+    let input = if is_mpasm || selects_mpasm(&input) {
+        mpasm(&input).unwrap_or_else(|err| fail(&in_path, &err))
+    } else {
         input
     };
+    let input = normalize(&input);
+    let tr_unit = assemble(&input, &in_path)
+        .unwrap_or_else(|err| fail(&in_path, &err));
 
-    println!("{}", parse_tr_unit(&input).unwrap());
+    for warning in lint(&tr_unit) {
+        eprintln!("{}: {}", in_path, warning);
+    }
+
+    if outputs.is_empty() {
+        println!("{:?}", tr_unit);
+    }
+    for (kind, out_path) in outputs {
+        let data = match kind.as_str() {
+            "--hex" => hex(&tr_unit).into_bytes(),
+            "--listing" => listing(&tr_unit).into_bytes(),
+            "--map" => map(&tr_unit).into_bytes(),
+            "--debug" => debug_info(&tr_unit).into_bytes(),
+            "--elf" => elf(&tr_unit),
+            "--coff" => coff(&tr_unit),
+            _ => unreachable!(),
+        };
+        File::create(&out_path)
+            .and_then(|mut out| out.write_all(&data))
+            .unwrap_or_else(|err| fail(&out_path, &err.to_string()));
+    }
 }
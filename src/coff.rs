@@ -0,0 +1,348 @@
+//! Microchip COFF (the v1 layout that gputils and MPLAB read). Program
+//! memory goes in a single `.code` section stored two bytes per word, with
+//! byte address = word address * 2. User IDs, config words and data EEPROM
+//! follow as absolute `.user_id`, `.config` and `.eeprom` sections at their
+//! HEX addresses, laid out the same way. Labels become external symbols and
+//! each statement gets a line-number entry pointing at the `.file` symbol.
+//!
+//! The optional header's processor type comes from the `device` directive,
+//! and is zero without one.
+
+use TrUnit;
+
+const MICROCHIP_MAGIC_V1: u16 = 0x1234;
+const OPTMAGIC_V1: u16 = 0x5678;
+const F_EXEC: u16 = 0x0002;
+const STYP_TEXT: u32 = 0x0020;
+const STYP_ABS: u32 = 0x1000;
+
+const C_EXT: u8 = 2;
+const C_FILE: u8 = 103;
+const C_SECTION: u8 = 104;
+
+const FILE_HDR_SIZE: usize = 20;
+const OPT_HDR_SIZE: usize = 16;
+const SEC_HDR_SIZE: usize = 40;
+const LINENO_SIZE: usize = 16;
+const SYM_SIZE: usize = 18;
+
+fn push_u16(buf: &mut Vec<u8>, x: u16) {
+    buf.extend_from_slice(&[x as u8, (x >> 8) as u8]);
+}
+
+fn push_u32(buf: &mut Vec<u8>, x: u32) {
+    push_u16(buf, x as u16);
+    push_u16(buf, (x >> 16) as u16);
+}
+
+/// Symbol and section names longer than eight bytes live in the string
+/// table, which starts with its own length.
+fn push_name(buf: &mut Vec<u8>, strtab: &mut Vec<u8>, name: &str) {
+    if name.len() <= 8 {
+        let mut field = [0; 8];
+        field[..name.len()].copy_from_slice(name.as_bytes());
+        buf.extend_from_slice(&field);
+    } else {
+        push_u32(buf, 0);
+        push_u32(buf, (strtab.len() + 4) as u32);
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+}
+
+fn push_sym(
+    buf: &mut Vec<u8>,
+    strtab: &mut Vec<u8>,
+    name: &str,
+    value: u32,
+    scnum: i16,
+    sclass: u8,
+    numaux: u8,
+) {
+    push_name(buf, strtab, name);
+    push_u32(buf, value);
+    push_u16(buf, scnum as u16);
+    push_u16(buf, 0); // n_type
+    buf.push(sclass);
+    buf.push(numaux);
+}
+
+pub(crate) fn coff(tr_unit: &TrUnit, path: &str) -> Vec<u8> {
+    let mut code = vec![];
+    for stmt in tr_unit.stmts() {
+        push_u16(&mut code, stmt.insn.encode());
+    }
+    // (name, byte address, flags, contents)
+    let mut sections = vec![(".code", 0, STYP_TEXT, code)];
+    for (name, addr, words) in tr_unit.image().sections() {
+        let mut data = vec![];
+        for word in words {
+            push_u16(&mut data, word);
+        }
+        let addr = u32::from(addr) * 2;
+        sections.push((name, addr, STYP_TEXT | STYP_ABS, data));
+    }
+    let nlnno = tr_unit.stmts().len();
+
+    let mut lines = vec![];
+    for stmt in tr_unit.stmts() {
+        push_u32(&mut lines, 0); // l_srcndx: the .file symbol
+        push_u16(&mut lines, stmt.span.line as u16);
+        push_u32(&mut lines, u32::from(stmt.addr) * 2);
+        push_u16(&mut lines, 0); // l_flags
+        push_u32(&mut lines, 0); // l_fcnndx
+    }
+
+    let mut strtab = vec![];
+    let mut syms = vec![];
+    let mut nsyms = 0;
+
+    // .file and its aux entry, which names the file via the string table
+    push_sym(&mut syms, &mut strtab, ".file", 0, -2, C_FILE, 1);
+    push_u32(&mut syms, (strtab.len() + 4) as u32);
+    strtab.extend_from_slice(path.as_bytes());
+    strtab.push(0);
+    syms.extend_from_slice(&[0; SYM_SIZE - 4]);
+    nsyms += 2;
+
+    // each section and its aux entry: length, relocation and line-number
+    // counts
+    for (i, &(name, addr, _, ref data)) in sections.iter().enumerate() {
+        let scnum = i as i16 + 1;
+        push_sym(&mut syms, &mut strtab, name, addr, scnum, C_SECTION, 1);
+        push_u32(&mut syms, data.len() as u32);
+        push_u16(&mut syms, 0);
+        push_u16(&mut syms, if i == 0 { nlnno as u16 } else { 0 });
+        syms.extend_from_slice(&[0; SYM_SIZE - 8]);
+        nsyms += 2;
+    }
+
+    for (name, addr) in tr_unit.labels() {
+        let value = u32::from(addr) * 2;
+        push_sym(&mut syms, &mut strtab, &name, value, 1, C_EXT, 0);
+        nsyms += 1;
+    }
+
+    let mut data_ptr = FILE_HDR_SIZE + OPT_HDR_SIZE
+        + sections.len() * SEC_HDR_SIZE;
+    let mut data_ptrs = vec![];
+    for section in &sections {
+        data_ptrs.push(data_ptr);
+        data_ptr += section.3.len();
+    }
+    let lnno_ptr = data_ptr;
+    let sym_ptr = lnno_ptr + nlnno * LINENO_SIZE;
+
+    let proc_type = tr_unit.device.map_or(0, |dev| dev.coff_id);
+    let mut out = vec![];
+
+    // file header
+    push_u16(&mut out, MICROCHIP_MAGIC_V1);
+    push_u16(&mut out, sections.len() as u16); // f_nscns
+    push_u32(&mut out, 0); // f_timdat
+    push_u32(&mut out, sym_ptr as u32);
+    push_u32(&mut out, nsyms);
+    push_u16(&mut out, OPT_HDR_SIZE as u16);
+    push_u16(&mut out, F_EXEC);
+
+    // optional header
+    push_u16(&mut out, OPTMAGIC_V1);
+    push_u16(&mut out, 0); // vstamp
+    push_u32(&mut out, proc_type);
+    push_u32(&mut out, 14); // rom_width_bits
+    push_u32(&mut out, 8); // ram_width_bits
+
+    // section headers; only .code has line numbers
+    let mut sec_strtab = vec![];
+    for (i, &(name, addr, flags, ref data)) in sections.iter().enumerate() {
+        push_name(&mut out, &mut sec_strtab, name);
+        push_u32(&mut out, addr); // s_paddr
+        push_u32(&mut out, addr); // s_vaddr
+        push_u32(&mut out, data.len() as u32);
+        push_u32(&mut out, data_ptrs[i] as u32);
+        push_u32(&mut out, 0); // s_relptr
+        push_u32(&mut out, if i == 0 { lnno_ptr as u32 } else { 0 });
+        push_u16(&mut out, 0); // s_nreloc
+        push_u16(&mut out, if i == 0 { nlnno as u16 } else { 0 });
+        push_u32(&mut out, flags);
+    }
+
+    for (_, _, _, data) in sections {
+        out.extend(data);
+    }
+    out.extend(lines);
+    out.extend(syms);
+    push_u32(&mut out, (strtab.len() + 4) as u32);
+    out.extend(strtab);
+
+    out
+}
+
+#[cfg(test)]
+pub(crate) struct CoffSection {
+    pub(crate) name: String,
+    pub(crate) paddr: u32,
+    pub(crate) data: Vec<u8>,
+    /// (line, byte address)
+    pub(crate) lines: Vec<(u16, u32)>,
+}
+
+#[cfg(test)]
+pub(crate) struct CoffSymbol {
+    pub(crate) name: String,
+    pub(crate) value: u32,
+    pub(crate) scnum: i16,
+    pub(crate) sclass: u8,
+}
+
+#[cfg(test)]
+pub(crate) struct Coff {
+    pub(crate) sections: Vec<CoffSection>,
+    /// Aux entries are skipped.
+    pub(crate) symbols: Vec<CoffSymbol>,
+}
+
+/// Reads back what `coff` writes.
+#[cfg(test)]
+pub(crate) fn read_coff(buf: &[u8]) -> Result<Coff, String> {
+    fn bytes(buf: &[u8], i: usize, n: usize) -> Result<&[u8], String> {
+        buf.get(i..i + n).ok_or_else(|| format!("truncated at {}", i))
+    }
+    fn u16_at(buf: &[u8], i: usize) -> Result<u16, String> {
+        let b = bytes(buf, i, 2)?;
+        Ok(u16::from(b[0]) | u16::from(b[1]) << 8)
+    }
+    fn u32_at(buf: &[u8], i: usize) -> Result<u32, String> {
+        Ok(u32::from(u16_at(buf, i)?) | u32::from(u16_at(buf, i + 2)?) << 16)
+    }
+    fn name_at(buf: &[u8], i: usize, strtab: usize) -> Result<String, String> {
+        let field = bytes(buf, i, 8)?;
+        let name = if u32_at(field, 0)? == 0 {
+            let start = strtab + u32_at(field, 4)? as usize;
+            let rest = buf.get(start..).ok_or("bad string table offset")?;
+            &rest[..rest.iter().position(|&b| b == 0).unwrap_or(rest.len())]
+        } else {
+            &field[..field.iter().position(|&b| b == 0).unwrap_or(8)]
+        };
+        String::from_utf8(name.to_vec()).map_err(|e| format!("{}", e))
+    }
+
+    if u16_at(buf, 0)? != MICROCHIP_MAGIC_V1 {
+        return Err("not a Microchip COFF v1 file".to_string());
+    }
+    let nscns = u16_at(buf, 2)? as usize;
+    let symptr = u32_at(buf, 8)? as usize;
+    let nsyms = u32_at(buf, 12)? as usize;
+    let opthdr = u16_at(buf, 16)? as usize;
+    let strtab = symptr + nsyms * SYM_SIZE;
+
+    let mut sections = vec![];
+    for i in 0..nscns {
+        let hdr = FILE_HDR_SIZE + opthdr + i * SEC_HDR_SIZE;
+        let size = u32_at(buf, hdr + 16)? as usize;
+        let scnptr = u32_at(buf, hdr + 20)? as usize;
+        let lnnoptr = u32_at(buf, hdr + 28)? as usize;
+        let nlnno = u16_at(buf, hdr + 34)? as usize;
+        let mut lines = vec![];
+        for j in 0..nlnno {
+            let entry = lnnoptr + j * LINENO_SIZE;
+            lines.push((u16_at(buf, entry + 4)?, u32_at(buf, entry + 6)?));
+        }
+        sections.push(CoffSection {
+            name: name_at(buf, hdr, strtab)?,
+            paddr: u32_at(buf, hdr + 8)?,
+            data: bytes(buf, scnptr, size)?.to_vec(),
+            lines,
+        });
+    }
+
+    let mut symbols = vec![];
+    let mut i = 0;
+    while i < nsyms {
+        let sym = symptr + i * SYM_SIZE;
+        let numaux = bytes(buf, sym + 17, 1)?[0] as usize;
+        symbols.push(CoffSymbol {
+            name: name_at(buf, sym, strtab)?,
+            value: u32_at(buf, sym + 8)?,
+            scnum: u16_at(buf, sym + 12)? as i16,
+            sclass: bytes(buf, sym + 16, 1)?[0],
+        });
+        i += 1 + numaux;
+    }
+
+    Ok(Coff { sections, symbols })
+}
+
+#[cfg(test)]
+#[test]
+fn test_coff_round_trip() {
+    let input = "start: movlw 0\n\n\na_long_label: movlw 0\n";
+    let tr_unit = ::build_tr_unit(input, "blink.asm").unwrap();
+
+    let bytes = coff(&tr_unit, "blink.asm");
+    assert_eq!(bytes[..FILE_HDR_SIZE + OPT_HDR_SIZE], [
+        0x34, 0x12, // f_magic
+        0x01, 0x00, // f_nscns
+        0x00, 0x00, 0x00, 0x00, // f_timdat
+        0x70, 0x00, 0x00, 0x00, // f_symptr
+        0x06, 0x00, 0x00, 0x00, // f_nsyms
+        0x10, 0x00, // f_opthdr
+        0x02, 0x00, // f_flags
+        0x78, 0x56, // opt_magic
+        0x00, 0x00, // vstamp
+        0x00, 0x00, 0x00, 0x00, // proc_type
+        0x0E, 0x00, 0x00, 0x00, // rom_width_bits
+        0x08, 0x00, 0x00, 0x00, // ram_width_bits
+    ]);
+
+    let with_device =
+        ::build_tr_unit("device PIC16F1829\n    nop\n", "blink.asm")
+        .unwrap();
+    let proc_type = FILE_HDR_SIZE + 4;
+    assert_eq!(
+        coff(&with_device, "blink.asm")[proc_type..proc_type + 4],
+        [0x29, 0x18, 0x0F, 0x00],
+    );
+
+    let with_image = ::build_tr_unit("\
+device PIC16F1829
+config 0x8007, 0x3FFC
+config 0x8008, 0x1FFF
+idlocs 1, 2
+de 0x42
+    nop
+", "blink.asm").unwrap();
+    let image = read_coff(&coff(&with_image, "blink.asm")).unwrap();
+    let sections: Vec<_> = image.sections.iter()
+        .map(|sec| (&sec.name[..], sec.paddr, &sec.data[..]))
+        .collect();
+    assert_eq!(sections, vec![
+        (".code", 0, &[0, 0][..]),
+        (".user_id", 0x10000, &[1, 0, 2, 0][..]),
+        (".config", 0x1000E, &[0xFC, 0x3F, 0xFF, 0x1F][..]),
+        (".eeprom", 0x1E000, &[0x42, 0][..]),
+    ]);
+    assert_eq!(image.sections[0].lines, vec![(6, 0)]);
+
+    let coff = read_coff(&bytes).unwrap();
+
+    assert_eq!(coff.sections.len(), 1);
+    let code = &coff.sections[0];
+    assert_eq!(code.name, ".code");
+    assert_eq!(code.paddr, 0);
+    assert_eq!(code.data, vec![0x00, 0x30, 0x00, 0x30]);
+    assert_eq!(code.lines, vec![(1, 0), (4, 2)]);
+
+    let syms: Vec<_> = coff.symbols.iter()
+        .map(|sym| (&sym.name[..], sym.value, sym.scnum, sym.sclass))
+        .collect();
+    assert_eq!(
+        syms,
+        vec![
+            (".file", 0, -2, C_FILE),
+            (".code", 0, 1, C_SECTION),
+            ("a_long_label", 2, 1, C_EXT),
+            ("start", 0, 1, C_EXT),
+        ],
+    );
+}
@@ -0,0 +1,432 @@
+//! Best- and worst-case cycle counts, for code with exact timing.
+//!
+//! `cycles(a, b)` is the worst case from `a` until control first gets to
+//! `b`, and `min_cycles(a, b)` the best case. With one argument, they count
+//! the routine at `a`, up to and including its `return`. A call counts as
+//! the `call` plus the routine it calls.
+//!
+//! Every backward jump needs a `repeats` annotation saying how many times
+//! it's taken each time its loop is entered. A loop has to run from the
+//! target of its backward jump down to the jump, and be entered at the
+//! top, and `b` can't be inside one. `callw` and `brw` need `targets`, as
+//! for `stack`. Where control goes comes from the graph in `flow`.
+
+use data::{Cycles, Flow};
+use flow::Cfg;
+use std::collections::BTreeMap;
+use {Stmt, TrUnit};
+
+/// (best, worst)
+type Range = (u64, u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Next {
+    Addr(u16),
+    /// Out of the routine
+    Return,
+}
+
+/// Where control can go from each instruction, and what getting there
+/// costs.
+type Graph = BTreeMap<u16, Vec<(Next, Range)>>;
+
+fn merge(exits: &mut BTreeMap<Next, Range>, next: Next, cost: Range) {
+    let range = exits.entry(next).or_insert(cost);
+    *range = (range.0.min(cost.0), range.1.max(cost.1));
+}
+
+pub(crate) struct Analysis<'a, 's: 'a> {
+    tr_unit: &'a TrUnit<'s>,
+    cfg: Cfg,
+    /// Cost of each routine, once known
+    routines: BTreeMap<u16, Range>,
+    /// Routines being analyzed, to catch recursion
+    active: Vec<u16>,
+}
+
+impl<'a, 's> Analysis<'a, 's> {
+    pub(crate) fn new(tr_unit: &'a TrUnit<'s>) -> Self {
+        Analysis {
+            tr_unit,
+            cfg: Cfg::new(tr_unit),
+            routines: BTreeMap::new(),
+            active: vec![],
+        }
+    }
+
+    fn stmt(&self, addr: u16) -> Result<&'a Stmt<'s>, String> {
+        // Addresses are assigned in order from zero.
+        self.tr_unit.stmts().get(addr as usize).ok_or_else(|| format!(
+            "control can run past the end of the program at 0x{:04X}", addr,
+        ))
+    }
+
+    /// The cheapest and dearest of calling each of `callees`.
+    fn calls(&mut self, callees: &[u16]) -> Result<Range, String> {
+        let mut range = None;
+        for &callee in callees {
+            let (best, worst) = self.routine(callee)?;
+            let (b, w) = range.unwrap_or((best, worst));
+            range = Some((b.min(best), w.max(worst)));
+        }
+        Ok(range.unwrap_or((0, 0)))
+    }
+
+    fn edges(&mut self, stmt: &Stmt) -> Result<Vec<(Next, Range)>, String> {
+        let addr = stmt.addr;
+        let line = stmt.span.line;
+        let desc = stmt.insn.desc;
+        let is_indirect =
+            [Flow::IndirectBranch, Flow::IndirectCall].contains(&desc.flow);
+        if is_indirect && self.tr_unit.indirect_targets(addr).is_empty() {
+            return Err(format!(
+                "{} on line {} needs a targets annotation",
+                desc.mnemonic, line,
+            ));
+        }
+        if stmt.insn.writes_f() && stmt.insn.operands[0].raw & 0x7F == 0x02 {
+            return Err(format!(
+                "can't follow the write to PCL on line {}; use brw", line,
+            ));
+        }
+        if desc.mnemonic == "_data_" {
+            return Err(format!("control can run into data on line {}", line));
+        }
+        let next = self.cfg.next[addr as usize].clone();
+        let n = match desc.cycles {
+            Cycles::Fixed(n) => u64::from(n),
+            // one cycle to carry on, two to skip
+            Cycles::Skip => return Ok(next.into_iter()
+                .map(|to| {
+                    let n = if to == addr + 1 { 1 } else { 2 };
+                    (Next::Addr(to), (n, n))
+                })
+                .collect()),
+        };
+        let callees = self.cfg.calls[addr as usize].clone();
+        let (best, worst) = self.calls(&callees)?;
+        let mut edges: Vec<_> = next.into_iter()
+            .map(|to| (Next::Addr(to), (n + best, n + worst)))
+            .collect();
+        if desc.flow == Flow::Return {
+            edges.push((Next::Return, (n, n)));
+        }
+        Ok(edges)
+    }
+
+    /// Everything reachable from `entry` without going past `stop`.
+    fn graph(&mut self, entry: u16, stop: Option<u16>)
+        -> Result<Graph, String>
+    {
+        let mut graph = Graph::new();
+        let mut todo = vec![entry];
+        while let Some(addr) = todo.pop() {
+            if graph.contains_key(&addr) || Some(addr) == stop {
+                continue;
+            }
+            let edges = self.edges(self.stmt(addr)?)?;
+            for &(next, _) in &edges {
+                if let Next::Addr(next) = next {
+                    todo.push(next);
+                }
+            }
+            graph.insert(addr, edges);
+        }
+        Ok(graph)
+    }
+
+    /// Replaces each loop with its head, whose edges are then the ways out
+    /// of the loop, costing the whole loop. `stop` can't be inside a loop,
+    /// since getting there doesn't take the whole loop.
+    fn collapse_loops(&self, graph: &mut Graph, stop: Option<u16>)
+        -> Result<(), String>
+    {
+        let mut loops = vec![];
+        for (&addr, edges) in graph.iter() {
+            let line = self.stmt(addr)?.span.line;
+            let backward: Vec<_> = edges.iter()
+                .filter_map(|&(next, _)| match next {
+                    Next::Addr(head) if head <= addr
+                        && graph.contains_key(&head) => Some(head),
+                    _ => None,
+                })
+                .collect();
+            match backward[..] {
+                [] => continue,
+                [head] => loops.push((head, addr, line)),
+                _ => return Err(format!(
+                    "brw on line {} can jump back to several places", line,
+                )),
+            }
+        }
+        loops.sort_by_key(|&(head, latch, _)| latch - head);
+
+        for (i, &(head, latch, line)) in loops.iter().enumerate() {
+            let &(lo, hi) = self.tr_unit.repeats.get(&latch).ok_or_else(|| {
+                format!("the backward jump on line {} needs repeats", line)
+            })?;
+            if loops[..i].iter().any(|&(h, _, _)| h == head) {
+                return Err(format!(
+                    "the loop ending on line {} shares its top with another",
+                    line,
+                ));
+            }
+            if !graph.contains_key(&head) || !graph.contains_key(&latch) {
+                return Err(format!(
+                    "the loop ending on line {} overlaps another", line,
+                ));
+            }
+
+            let within = |addr: u16| head < addr && addr <= latch;
+            if let Some(stop) = stop.filter(|&stop| within(stop)) {
+                return Err(format!(
+                    "can't count up to {}, inside the loop ending on line {}",
+                    self.tr_unit.addr_name(stop), line,
+                ));
+            }
+            let mut exits = paths(graph, head, &within);
+            let (once_best, once_worst) =
+                exits.remove(&Next::Addr(head)).unwrap_or((0, 0));
+            if exits.is_empty() {
+                return Err(format!(
+                    "the loop ending on line {} never exits", line,
+                ));
+            }
+            let mut edges = vec![];
+            for (next, (best, worst)) in exits {
+                if let Next::Addr(addr) = next {
+                    if addr < head {
+                        return Err(format!(
+                            "jumping backward out of the loop ending on line \
+                                {} isn't supported",
+                            line,
+                        ));
+                    }
+                }
+                edges.push((next, (
+                    lo * once_best + best,
+                    hi * once_worst + worst,
+                )));
+            }
+
+            let body: Vec<_> = graph.range(head + 1..=latch)
+                .map(|(&addr, _)| addr)
+                .collect();
+            for addr in body {
+                graph.remove(&addr);
+            }
+            graph.insert(head, edges);
+            for (&addr, edges) in graph.iter() {
+                let into_body = edges.iter().any(|&(next, _)| {
+                    matches!(next, Next::Addr(next) if within(next))
+                });
+                if into_body {
+                    return Err(format!(
+                        "line {} jumps into the middle of the loop ending on \
+                            line {}",
+                        self.stmt(addr)?.span.line, line,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Best and worst case from `entry` to each way out of `graph`.
+    fn exits(&mut self, entry: u16, stop: Option<u16>)
+        -> Result<BTreeMap<Next, Range>, String>
+    {
+        let mut graph = self.graph(entry, stop)?;
+        self.collapse_loops(&mut graph, stop)?;
+        let inside = |addr| addr != entry && graph.contains_key(&addr);
+        Ok(paths(&graph, entry, &inside))
+    }
+
+    /// Cost of the routine at `entry`, up to and including its return.
+    pub(crate) fn routine(&mut self, entry: u16) -> Result<Range, String> {
+        if let Some(&range) = self.routines.get(&entry) {
+            return Ok(range);
+        }
+        if self.active.contains(&entry) {
+            return Err(format!(
+                "{} is recursive", self.tr_unit.addr_name(entry),
+            ));
+        }
+        self.active.push(entry);
+        let exits = self.exits(entry, None);
+        self.active.pop();
+        let range = exits?.get(&Next::Return).cloned().ok_or_else(|| {
+            format!("{} never returns", self.tr_unit.addr_name(entry))
+        })?;
+        self.routines.insert(entry, range);
+        Ok(range)
+    }
+
+    /// Cost from `from` until control first gets to `to`. Paths that
+    /// return first don't count.
+    pub(crate) fn region(&mut self, from: u16, to: u16)
+        -> Result<Range, String>
+    {
+        if from == to {
+            return Err("a region needs two different addresses".to_string());
+        }
+        let exits = self.exits(from, Some(to))?;
+        exits.get(&Next::Addr(to)).cloned().ok_or_else(|| format!(
+            "{} never gets to {}",
+            self.tr_unit.addr_name(from),
+            self.tr_unit.addr_name(to),
+        ))
+    }
+
+    /// `cycles` and `min_cycles`, for `expr::eval_with`.
+    pub(crate) fn funcs(&mut self, name: &str, args: &[i64])
+        -> Option<Result<i64, String>>
+    {
+        if name != "cycles" && name != "min_cycles" {
+            return None;
+        }
+        let mut addrs = vec![];
+        for &arg in args {
+            if !(0..0x8000).contains(&arg) {
+                return Some(Err(format!(
+                    "0x{:X} isn't a program address", arg,
+                )));
+            }
+            addrs.push(arg as u16);
+        }
+        let range = match addrs[..] {
+            [entry] => self.routine(entry),
+            [from, to] => self.region(from, to),
+            _ => Err(format!("'{}' takes one or two labels", name)),
+        };
+        Some(range.map(|(best, worst)| {
+            (if name == "cycles" { worst } else { best }) as i64
+        }))
+    }
+}
+
+/// Best and worst case from `entry` to each way out of the nodes `inside`
+/// (where `entry` itself doesn't count as inside). Everything inside has
+/// to be forward of `entry` and loop-free.
+fn paths(graph: &Graph, entry: u16, inside: &dyn Fn(u16) -> bool)
+    -> BTreeMap<Next, Range>
+{
+    fn visit(
+        graph: &Graph,
+        addr: u16,
+        inside: &dyn Fn(u16) -> bool,
+        memo: &mut BTreeMap<u16, BTreeMap<Next, Range>>,
+    ) {
+        if memo.contains_key(&addr) {
+            return;
+        }
+        let mut exits = BTreeMap::new();
+        for &(next, (best, worst)) in &graph[&addr] {
+            match next {
+                Next::Addr(next) if next > addr && inside(next) => {
+                    visit(graph, next, inside, memo);
+                    for (&exit, &(b, w)) in &memo[&next] {
+                        merge(&mut exits, exit, (best + b, worst + w));
+                    }
+                },
+                next => merge(&mut exits, next, (best, worst)),
+            }
+        }
+        memo.insert(addr, exits);
+    }
+
+    let mut memo = BTreeMap::new();
+    visit(graph, entry, inside, &mut memo);
+    memo.remove(&entry).unwrap()
+}
+
+/// One line for each routine that something calls.
+pub(crate) fn lines(tr_unit: &TrUnit) -> Vec<String> {
+    let mut analysis = Analysis::new(tr_unit);
+    let mut callees: Vec<_> =
+        analysis.cfg.calls.iter().flatten().cloned().collect();
+    callees.sort();
+    callees.dedup();
+
+    callees.into_iter()
+        .map(|callee| {
+            let name = tr_unit.addr_name(callee);
+            match analysis.routine(callee) {
+                Ok((best, worst)) if best == worst =>
+                    format!("Cycles in {}: {}", name, best),
+                Ok((best, worst)) =>
+                    format!("Cycles in {}: {} to {}", name, best, worst),
+                Err(msg) => format!("Cycles in {}: unknown ({})", name, msg),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[test]
+fn test_cycles() {
+    let src = |repeats, body| format!("\
+a:
+    movlw 3
+    movwf 0x70
+loop:
+    decfsz 0x70, F
+    {}
+    bra loop
+    call d
+    return
+d:
+    {}
+    return
+", repeats, body);
+
+    let src_unknown = src("", "nop");
+    let tr_unit = ::build_tr_unit(&src_unknown, "test.asm").unwrap();
+    assert!(Analysis::new(&tr_unit).routine(0).unwrap_err()
+        .contains("needs repeats"));
+
+    // Three times round: twice through the bra, then skipping it.
+    let src_fixed = src("repeats 2", "nop");
+    let tr_unit = ::build_tr_unit(&src_fixed, "test.asm").unwrap();
+    let mut analysis = Analysis::new(&tr_unit);
+    assert_eq!(analysis.routine(6), Ok((3, 3)));
+    assert_eq!(analysis.routine(0), Ok((17, 17)));
+    assert_eq!(analysis.region(0, 4), Ok((10, 10)));
+    assert!(analysis.region(4, 0).is_err());
+
+    let src_range = src("repeats 1, 2", "nop");
+    let tr_unit = ::build_tr_unit(&src_range, "test.asm").unwrap();
+    let mut analysis = Analysis::new(&tr_unit);
+    assert_eq!(analysis.funcs("min_cycles", &[0, 4]), Some(Ok(7)));
+    assert_eq!(analysis.funcs("cycles", &[0, 4]), Some(Ok(10)));
+    assert_eq!(analysis.funcs("low", &[0]), None);
+    assert_eq!(lines(&tr_unit), vec!["Cycles in d: 3"]);
+
+    let src_asserts = format!(
+        "{}assert cycles(a, d - 2) == 10\n\
+            assert min_cycles(a, d - 2) > 7, \"too fast\"\n",
+        src_range,
+    );
+    assert_eq!(
+        ::build_tr_unit(&src_asserts, "test.asm").unwrap_err(),
+        "line 14: assertion failed: too fast",
+    );
+
+    let src_inside = "\
+loop:
+    nop
+    btfsc 0x70, 0
+x:
+    nop
+    nop
+    repeats 2
+    bra loop
+    assert cycles(loop, x) == 2
+";
+    assert!(::build_tr_unit(src_inside, "test.asm").unwrap_err()
+        .contains("can't count up to x, inside the loop ending on line 8"));
+
+    let src_pcl = src("repeats 2", "movwf 0x02");
+    let tr_unit = ::build_tr_unit(&src_pcl, "test.asm").unwrap();
+    assert!(Analysis::new(&tr_unit).routine(0).unwrap_err().contains("PCL"));
+}
@@ -13,7 +13,7 @@ pub(crate) struct Insn {
 }
 
 impl Insn {
-    fn encode(&self) -> u16 {
+    pub(crate) fn encode(&self) -> u16 {
         // TODO: Do we want to precompute or at least cache this?
         let mut fields: Vec<_> = self.desc.operands
             .iter()
@@ -26,7 +26,6 @@ impl Insn {
         let mut word = 0;
         for (field_desc, opd) in fields {
             let width = field_desc.kind.width();
-            println!("width: {}", width);
             word <<= width;
             assert_eq!(((1 << width) - 1) & word, 0);
             word |= opd.raw;
@@ -34,6 +33,53 @@ impl Insn {
         word |= self.desc.opcode;
         word
     }
+
+    /// Where a `Flow::Branch` or `Flow::Call` at `addr` goes. `goto` and
+    /// `call` are taken to stay in the same page, i.e. PCLATH is right.
+    pub(crate) fn target(&self, addr: u16) -> u16 {
+        let raw = self.operands[0].raw;
+        match self.desc.operands.first().map(|opd| opd.kind) {
+            Some(RPK(n)) => {
+                let shift = 16 - n;
+                let offset = ((raw << shift) as i16 >> shift) as u16;
+                addr.wrapping_add(1).wrapping_add(offset)
+            },
+            _ => (addr & 0x7800) | raw,
+        }
+    }
+
+    fn has(&self, regs: Regs, w: bool) -> bool {
+        match regs {
+            Regs::None => false,
+            Regs::W => w,
+            Regs::F => !w,
+            Regs::WF => true,
+            Regs::D => {
+                let d = self.desc.operands.iter()
+                    .position(|opd| matches!(opd.kind, D))
+                    .map_or(1, |i| self.operands[i].raw);
+                (d == 0) == w
+            },
+        }
+    }
+
+    pub(crate) fn reads_w(&self) -> bool {
+        self.has(self.desc.reads, true)
+    }
+
+    pub(crate) fn reads_f(&self) -> bool {
+        self.has(self.desc.reads, false)
+    }
+
+    /// Taking the `d` operand into account
+    pub(crate) fn writes_w(&self) -> bool {
+        self.has(self.desc.writes, true)
+    }
+
+    /// Taking the `d` operand into account
+    pub(crate) fn writes_f(&self) -> bool {
+        self.has(self.desc.writes, false)
+    }
 }
 
 #[cfg(test)]
@@ -52,12 +98,20 @@ fn round_trip() {
     }
 }
 
+/// An instruction: how to write it and encode it, and what it does. Lints
+/// and analyses go by the last few fields rather than lists of mnemonics.
 #[derive(Clone)]
 pub(crate) struct InsnDesc {
     pub(crate) mnemonic: &'static str,
     pub(crate) syntax: Syntax,
     pub(crate) operands: &'static [OpdDesc], // opcode doesn't count
     pub(crate) opcode: u16,
+    /// STATUS bits it can change
+    pub(crate) status: &'static [Status],
+    pub(crate) reads: Regs,
+    pub(crate) writes: Regs,
+    pub(crate) flow: Flow,
+    pub(crate) cycles: Cycles,
 }
 
 impl fmt::Debug for InsnDesc {
@@ -88,10 +142,56 @@ impl Syntax {
     }
 }
 
+/// A STATUS bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Status {
+    C,
+    DC,
+    Z,
+}
+
+/// Which of W and the `f` operand an instruction reads or writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Regs {
+    None,
+    W,
+    F,
+    WF,
+    /// Whichever the `d` operand says
+    D,
+}
+
+/// Where control goes after an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Flow {
+    Fallthrough,
+    /// To the next instruction or the one after
+    Skip,
+    /// To the `k` operand
+    Branch,
+    /// To the `k` operand, then back to the next instruction
+    Call,
+    Return,
+    /// To wherever W says, relative to the next instruction (`brw`)
+    IndirectBranch,
+    /// `callw`, to wherever W and PCLATH say
+    IndirectCall,
+    /// Back to 0x0000, with everything reset
+    Reset,
+}
+
+/// How many instruction cycles (4 clocks each) an instruction takes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Cycles {
+    Fixed(u8),
+    /// 1 if the next instruction runs, 2 if it's skipped
+    Skip,
+}
+
 #[derive(Clone, Copy)]
 pub(crate) struct OpdDesc {
     field_idx: u8, // lsb to msb
-    kind: OpdDescKind,
+    pub(crate) kind: OpdDescKind,
 }
 
 /// Tells the assembler how to turn an operand into bits.
@@ -131,6 +231,30 @@ impl OpdDescKind {
         }
     }
 
+    /// Turns an operand value into field bits. `addr` is the address of the
+    /// instruction, for relative operands.
+    pub(crate) fn encode(&self, val: i64, addr: u16) -> Result<u16, String> {
+        let width = self.width();
+        let (val, min, max) = match *self {
+            F => (val, 0, 0xFFF), // any banked address; the bank is dropped
+            K(n) => (val, -(1i64 << n) + 1, (1i64 << n) - 1),
+            SK(n) => (val, -(1i64 << (n - 1)), (1i64 << (n - 1)) - 1),
+            APK(_) => (val, 0, 0x7FFF), // the page comes from PCLATH
+            RPK(n) => (
+                val - (i64::from(addr) + 1),
+                -(1i64 << (n - 1)),
+                (1i64 << (n - 1)) - 1,
+            ),
+            _ => (val, 0, (1i64 << width) - 1),
+        };
+        if val < min || val > max {
+            return Err(format!(
+                "operand {} is out of range {}..{}", val, min, max,
+            ));
+        }
+        Ok((val & ((1 << width) - 1)) as u16)
+    }
+
     pub(crate) fn data_type(&self) -> DataType {
         match *self {
             DC(_) => DataType::Invisible,
@@ -150,6 +274,19 @@ impl OpdDescKind {
     }
 }
 
+#[cfg(test)]
+#[test]
+fn test_encode_opd() {
+    assert_eq!(F.encode(0x0A5, 0), Ok(0x25));
+    assert_eq!(D.encode(1, 0), Ok(1));
+    assert!(B.encode(8, 0).is_err());
+    assert_eq!(K(8).encode(-1, 0), Ok(0xFF));
+    assert!(K(8).encode(0x100, 0).is_err());
+    assert_eq!(APK(11).encode(0x0805, 0), Ok(0x005));
+    assert_eq!(RPK(9).encode(0x10, 0x20), Ok(0x1EF));
+    assert!(RPK(9).encode(0x200, 0).is_err());
+}
+
 #[derive(Clone, Copy, Debug)]
 pub(crate) enum DataType {
     Invisible,
@@ -183,12 +320,12 @@ static K8_OPERANDS: &[OpdDesc] = &[
     OpdDesc { field_idx: 0, kind: K(8) },
 ];
 
-struct InsnDescTable {
+pub(crate) struct InsnDescTable {
     table: Vec<&'static InsnDesc>,
 }
 
 impl InsnDescTable {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         let mut table = vec![&INVALID_INSN_DESC; 0b100_0000_0000_0000];
         for desc in INSN_DESCS {
             let total_opd_width: usize =
@@ -205,7 +342,7 @@ impl InsnDescTable {
         Self { table }
     }
 
-    fn decode(&self, word: u16) -> Insn {
+    pub(crate) fn decode(&self, word: u16) -> Insn {
         let insn_desc = self.table[word as usize];
 
         // TODO: Do we want to precompute or at least cache this?
@@ -244,11 +381,53 @@ fn test_get_insn_desc_table() {
     assert_eq!(table.decode(0b00_0000_0000_0010).desc.mnemonic, "_invalid_");
 }
 
+#[cfg(test)]
+#[test]
+fn test_semantics() {
+    let table = InsnDescTable::new();
+    let decfsz_w = table.decode(0b00_1011_0111_0000); // decfsz 0x70, W
+    assert_eq!(decfsz_w.desc.flow, Flow::Skip);
+    assert!(decfsz_w.reads_f() && decfsz_w.writes_w() && !decfsz_w.writes_f());
+    let addwf_f = table.decode(0b00_0111_1010_0000); // addwf 0x20, F
+    assert!(addwf_f.reads_w() && addwf_f.writes_f() && !addwf_f.writes_w());
+    assert_eq!(addwf_f.desc.status, &[Status::C, Status::DC, Status::Z]);
+    let bra = table.decode(0b11_0011_1111_1110); // bra $-1
+    assert_eq!(bra.desc.flow, Flow::Branch);
+    assert_eq!(bra.target(0x10), 0x0F);
+    let call = table.decode(0b10_0001_0010_0011); // call 0x123
+    assert_eq!(call.target(0x0810), 0x0923);
+
+    for desc in INSN_DESCS {
+        let is_skip = desc.flow == Flow::Skip;
+        assert_eq!(desc.cycles == Cycles::Skip, is_skip, "{:?}", desc);
+        let has_d = desc.operands.iter().any(|opd| matches!(opd.kind, D));
+        assert_eq!(desc.writes == Regs::D, has_d, "{:?}", desc);
+    }
+}
+
 static INVALID_INSN_DESC: InsnDesc = InsnDesc {
     mnemonic: "_invalid_",
     syntax: Syntax::Normal,
     operands: &[],
     opcode: 0,
+    status: &[],
+    reads: Regs::None,
+    writes: Regs::None,
+    flow: Flow::Fallthrough,
+    cycles: Cycles::Fixed(1),
+};
+
+/// Not an instruction: a raw program memory word, for `da`.
+pub(crate) static DATA_WORD_DESC: InsnDesc = InsnDesc {
+    mnemonic: "_data_",
+    syntax: Syntax::Normal,
+    operands: &[OpdDesc { field_idx: 0, kind: UK(14) }],
+    opcode: 0,
+    status: &[],
+    reads: Regs::None,
+    writes: Regs::None,
+    flow: Flow::Fallthrough,
+    cycles: Cycles::Fixed(1),
 };
 
 pub(crate) static INSN_DESCS: &[InsnDesc] = &[
@@ -257,42 +436,77 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_0111_0000_0000,
+        status: &[Status::C, Status::DC, Status::Z],
+        reads: Regs::WF,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "addwfc",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b11_1101_0000_0000,
+        status: &[Status::C, Status::DC, Status::Z],
+        reads: Regs::WF,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "andwf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_0101_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::WF,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "asrf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b11_0111_0000_0000,
+        status: &[Status::C, Status::Z],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "lslf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b11_0101_0000_0000,
+        status: &[Status::C, Status::Z],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "lsrf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b11_0110_0000_0000,
+        status: &[Status::C, Status::Z],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "clrf",
         syntax: Syntax::Normal,
         operands: F_OPERANDS,
         opcode: 0b00_0001_1000_0000,
+        status: &[Status::Z],
+        reads: Regs::None,
+        writes: Regs::F,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "clrw",
@@ -301,78 +515,143 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 0, kind: DC(2) },
         ],
         opcode: 0b00_0001_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::None,
+        writes: Regs::W,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "comf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_1001_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "decf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_0011_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "incf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_1010_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "iorwf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_0100_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::WF,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "movf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_1000_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "movwf",
         syntax: Syntax::Normal,
         operands: F_OPERANDS,
         opcode: 0b00_0000_1000_0000,
+        status: &[],
+        reads: Regs::W,
+        writes: Regs::F,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "rlf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_1101_0000_0000,
+        status: &[Status::C],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "rrf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_1100_0000_0000,
+        status: &[Status::C],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "subwf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_0010_0000_0000,
+        status: &[Status::C, Status::DC, Status::Z],
+        reads: Regs::WF,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "subwfb",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b11_1011_0000_0000,
+        status: &[Status::C, Status::DC, Status::Z],
+        reads: Regs::WF,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "swapf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_1110_0000_0000,
+        status: &[],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "xorwf",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_0110_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::WF,
+        writes: Regs::D,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
 
     InsnDesc {
@@ -380,12 +659,22 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_1011_0000_0000,
+        status: &[],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Skip,
+        cycles: Cycles::Skip,
     },
     InsnDesc {
         mnemonic: "incfsz",
         syntax: Syntax::Normal,
         operands: FD_OPERANDS,
         opcode: 0b00_1111_0000_0000,
+        status: &[],
+        reads: Regs::F,
+        writes: Regs::D,
+        flow: Flow::Skip,
+        cycles: Cycles::Skip,
     },
 
     InsnDesc {
@@ -393,12 +682,22 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
         syntax: Syntax::Normal,
         operands: FB_OPERANDS,
         opcode: 0b01_0000_0000_0000,
+        status: &[],
+        reads: Regs::F,
+        writes: Regs::F,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "bsf",
         syntax: Syntax::Normal,
         operands: FB_OPERANDS,
         opcode: 0b01_0100_0000_0000,
+        status: &[],
+        reads: Regs::F,
+        writes: Regs::F,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
 
     InsnDesc {
@@ -406,12 +705,22 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
         syntax: Syntax::Normal,
         operands: FB_OPERANDS,
         opcode: 0b01_1000_0000_0000,
+        status: &[],
+        reads: Regs::F,
+        writes: Regs::None,
+        flow: Flow::Skip,
+        cycles: Cycles::Skip,
     },
     InsnDesc {
         mnemonic: "btfss",
         syntax: Syntax::Normal,
         operands: FB_OPERANDS,
         opcode: 0b01_1100_0000_0000,
+        status: &[],
+        reads: Regs::F,
+        writes: Regs::None,
+        flow: Flow::Skip,
+        cycles: Cycles::Skip,
     },
 
     InsnDesc {
@@ -419,18 +728,33 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
         syntax: Syntax::Normal,
         operands: K8_OPERANDS,
         opcode: 0b11_1110_0000_0000,
+        status: &[Status::C, Status::DC, Status::Z],
+        reads: Regs::W,
+        writes: Regs::W,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "andlw",
         syntax: Syntax::Normal,
         operands: K8_OPERANDS,
         opcode: 0b11_1001_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::W,
+        writes: Regs::W,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "iorlw",
         syntax: Syntax::Normal,
         operands: K8_OPERANDS,
         opcode: 0b11_1000_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::W,
+        writes: Regs::W,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "movlb",
@@ -439,6 +763,11 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 0, kind: A },
         ],
         opcode: 0b00_0000_0010_0000,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "movlp",
@@ -447,24 +776,44 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 0, kind: PCLATH },
         ],
         opcode: 0b11_0001_1000_0000,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "movlw",
         syntax: Syntax::Normal,
         operands: K8_OPERANDS,
         opcode: 0b11_0000_0000_0000,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::W,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "sublw",
         syntax: Syntax::Normal,
         operands: K8_OPERANDS,
         opcode: 0b11_1100_0000_0000,
+        status: &[Status::C, Status::DC, Status::Z],
+        reads: Regs::W,
+        writes: Regs::W,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "xorlw",
         syntax: Syntax::Normal,
         operands: K8_OPERANDS,
         opcode: 0b11_1010_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::W,
+        writes: Regs::W,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
 
     InsnDesc {
@@ -474,12 +823,22 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 0, kind: RPK(9) },
         ],
         opcode: 0b11_0010_0000_0000,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Branch,
+        cycles: Cycles::Fixed(2),
     },
     InsnDesc {
         mnemonic: "brw",
         syntax: Syntax::Normal,
         operands: &[],
         opcode: 0b00_0000_0000_1011,
+        status: &[],
+        reads: Regs::W,
+        writes: Regs::None,
+        flow: Flow::IndirectBranch,
+        cycles: Cycles::Fixed(2),
     },
     InsnDesc {
         mnemonic: "call",
@@ -488,12 +847,22 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 0, kind: APK(11) },
         ],
         opcode: 0b10_0000_0000_0000,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Call,
+        cycles: Cycles::Fixed(2),
     },
     InsnDesc {
         mnemonic: "callw",
         syntax: Syntax::Normal,
         operands: &[],
         opcode: 0b00_0000_0000_1010,
+        status: &[],
+        reads: Regs::W,
+        writes: Regs::None,
+        flow: Flow::IndirectCall,
+        cycles: Cycles::Fixed(2),
     },
     InsnDesc {
         mnemonic: "goto",
@@ -502,24 +871,44 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 0, kind: APK(11) },
         ],
         opcode: 0b10_1000_0000_0000,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Branch,
+        cycles: Cycles::Fixed(2),
     },
     InsnDesc {
         mnemonic: "retfie",
         syntax: Syntax::Normal,
         operands: &[],
         opcode: 0b00_0000_0000_1001,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Return,
+        cycles: Cycles::Fixed(2),
     },
     InsnDesc {
         mnemonic: "retlw",
         syntax: Syntax::Normal,
         operands: K8_OPERANDS,
         opcode: 0b11_0100_0000_0000,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::W,
+        flow: Flow::Return,
+        cycles: Cycles::Fixed(2),
     },
     InsnDesc {
         mnemonic: "return",
         syntax: Syntax::Normal,
         operands: &[],
         opcode: 0b00_0000_0000_1000,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Return,
+        cycles: Cycles::Fixed(2),
     },
 
     InsnDesc {
@@ -527,42 +916,77 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
         syntax: Syntax::Normal,
         operands: &[],
         opcode: 0b00_0000_0110_0100,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "nop",
         syntax: Syntax::Normal,
         operands: &[],
         opcode: 0b00_0000_0000_0000,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "reset",
         syntax: Syntax::Normal,
         operands: &[],
         opcode: 0b00_0000_0000_0001,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Reset,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "sleep",
         syntax: Syntax::Normal,
         operands: &[],
         opcode: 0b00_0000_0110_0011,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "tris_a",
         syntax: Syntax::Tris,
         operands: &[],
         opcode: 0b00_0000_0110_0101,
+        status: &[],
+        reads: Regs::W,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "tris_b",
         syntax: Syntax::Tris,
         operands: &[],
         opcode: 0b00_0000_0110_0110,
+        status: &[],
+        reads: Regs::W,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "tris_c",
         syntax: Syntax::Tris,
         operands: &[],
         opcode: 0b00_0000_0110_0111,
+        status: &[],
+        reads: Regs::W,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
 
     InsnDesc {
@@ -573,6 +997,11 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 0, kind: SK(6) }, // !!
         ],
         opcode: 0b11_0001_0000_0000,
+        status: &[],
+        reads: Regs::None,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "moviw_mm",
@@ -582,6 +1011,11 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 0, kind: MM }, // !!
         ],
         opcode: 0b00_0000_0001_0000,
+        status: &[Status::Z],
+        reads: Regs::None,
+        writes: Regs::W,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "moviw_off",
@@ -591,6 +1025,11 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 1, kind: FSRn },
         ],
         opcode: 0b11_1111_0000_0000,
+        status: &[Status::Z],
+        reads: Regs::None,
+        writes: Regs::W,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "movwi_mm",
@@ -600,6 +1039,11 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 0, kind: MM }, // !!
         ],
         opcode: 0b00_0000_0001_1000,
+        status: &[],
+        reads: Regs::W,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
     InsnDesc {
         mnemonic: "movwi_off",
@@ -609,5 +1053,10 @@ pub(crate) static INSN_DESCS: &[InsnDesc] = &[
             OpdDesc { field_idx: 1, kind: FSRn },
         ],
         opcode: 0b11_1111_1000_0000,
+        status: &[],
+        reads: Regs::W,
+        writes: Regs::None,
+        flow: Flow::Fallthrough,
+        cycles: Cycles::Fixed(1),
     },
 ];
@@ -0,0 +1,93 @@
+//! Source-line debug information, written as a JSON sidecar:
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "file": "blink.asm",
+//!   "lines": [{"addr": 0, "line": 2, "col": 8}, ...],
+//!   "labels": [{"name": "start", "addr": 0}, ...]
+//! }
+//! ```
+//!
+//! `lines` has one entry per program word, in address order. `line` and
+//! `col` are 1-based and point at the start of the statement. `labels` is
+//! sorted by name.
+
+use std::fmt::Write;
+use TrUnit;
+
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                write!(out, "\\u{:04x}", c as u32).unwrap();
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub(crate) fn debug_info(tr_unit: &TrUnit, path: &str) -> String {
+    let mut out = String::new();
+    writeln!(out, "{{").unwrap();
+    writeln!(out, "  \"version\": 1,").unwrap();
+    writeln!(out, "  \"file\": {},", json_str(path)).unwrap();
+
+    let lines: Vec<_> = tr_unit.stmts().iter()
+        .map(|stmt| format!(
+            "    {{\"addr\": {}, \"line\": {}, \"col\": {}}}",
+            stmt.addr, stmt.span.line, stmt.span.col,
+        ))
+        .collect();
+    writeln!(out, "  \"lines\": [").unwrap();
+    if !lines.is_empty() {
+        writeln!(out, "{}", lines.join(",\n")).unwrap();
+    }
+    writeln!(out, "  ],").unwrap();
+
+    let labels: Vec<_> = tr_unit.labels().into_iter()
+        .map(|(name, addr)| format!(
+            "    {{\"name\": {}, \"addr\": {}}}",
+            json_str(&name), addr,
+        ))
+        .collect();
+    writeln!(out, "  \"labels\": [").unwrap();
+    if !labels.is_empty() {
+        writeln!(out, "{}", labels.join(",\n")).unwrap();
+    }
+    writeln!(out, "  ]").unwrap();
+
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+#[cfg(test)]
+#[test]
+fn test_debug_info() {
+    let input = "start: nop\n goto start\n";
+    let tr_unit = ::build_tr_unit(input, "test.asm").unwrap();
+
+    assert_eq!(
+        debug_info(&tr_unit, "dir\\\"a\".asm"),
+        concat!(
+            "{\n",
+            "  \"version\": 1,\n",
+            "  \"file\": \"dir\\\\\\\"a\\\".asm\",\n",
+            "  \"lines\": [\n",
+            "    {\"addr\": 0, \"line\": 1, \"col\": 1},\n",
+            "    {\"addr\": 1, \"line\": 2, \"col\": 2}\n",
+            "  ],\n",
+            "  \"labels\": [\n",
+            "    {\"name\": \"start\", \"addr\": 0}\n",
+            "  ]\n",
+            "}\n",
+        ),
+    );
+}
@@ -0,0 +1,190 @@
+//! `delay` and `delay_cycles`: busy-waits of an exact number of instruction
+//! cycles, made of nested `decfsz` loops and some padding.
+//!
+//! Loading a counter `k` (1 to 256, where 256 is loaded as 0) and looping
+//! around a body of `b` cycles takes `k * (b + 3) + 1` cycles, so one loop
+//! takes `3k + 1`, two take `k2 * (3k1 + 4) + 1` and so on. The rest is
+//! made up with `bra $+1` (2 cycles) and `nop`. Each loop needs a scratch
+//! register from `delay_scratch`, outermost first, and W is clobbered.
+
+use pseudo::{branch_back, desc, Insns};
+use OpdSrc;
+
+/// Three loops is up to about 50 million cycles.
+const MAX_LOOPS: usize = 3;
+
+/// Loop counters, outermost first, and cycles of padding.
+#[derive(Debug, PartialEq)]
+struct Plan {
+    counts: Vec<u64>,
+    pad: u64,
+}
+
+/// Cycles taken by loops with `counts`, outermost first.
+fn loop_cycles(counts: &[u64]) -> u64 {
+    counts.iter().rev().fold(0, |body, &k| k * (body + 3) + 1)
+}
+
+impl Plan {
+    fn words(&self) -> u64 {
+        4 * self.counts.len() as u64 + self.pad / 2 + self.pad % 2
+    }
+}
+
+/// The shortest way to wait exactly `cycles` with at most `max_loops`
+/// loops.
+fn plan(cycles: u64, max_loops: usize) -> Plan {
+    let mut best = Plan { counts: vec![], pad: cycles };
+    let mut consider = |counts: Vec<u64>| {
+        let taken = loop_cycles(&counts);
+        if taken <= cycles {
+            let plan = Plan { counts, pad: cycles - taken };
+            if plan.words() < best.words() {
+                best = plan;
+            }
+        }
+    };
+    // The outermost counter is as big as fits; the inner ones are tried
+    // exhaustively for the smallest remainder.
+    let outer = |inner: &[u64]| {
+        let per = loop_cycles(inner) + 3;
+        let k = ((cycles.saturating_sub(1)) / per).clamp(1, 256);
+        let mut counts = vec![k];
+        counts.extend(inner);
+        counts
+    };
+    if max_loops >= 1 {
+        consider(outer(&[]));
+    }
+    if max_loops >= 2 {
+        for k1 in 1..=256 {
+            consider(outer(&[k1]));
+        }
+    }
+    if max_loops >= 3 {
+        for k2 in 1..=256 {
+            for k1 in 1..=256 {
+                consider(outer(&[k2, k1]));
+            }
+        }
+    }
+    best
+}
+
+/// Instruction cycles in `val` `unit`s at `clock` Hz, rounded to the
+/// nearest, and how far off that is in nanoseconds.
+pub(crate) fn cycles(val: i64, unit: &str, clock: Option<u64>)
+    -> Result<(u64, f64), String>
+{
+    let clock = clock.ok_or("a timed delay needs a clock setting")?;
+    if val < 0 {
+        return Err(format!("can't delay for {} {}", val, unit));
+    }
+    let ns_per_unit: u128 = match unit {
+        "s" => 1_000_000_000,
+        "ms" => 1_000_000,
+        "us" => 1_000,
+        _ => 1,
+    };
+    // Each instruction cycle is four clocks.
+    let num = val as u128 * ns_per_unit * u128::from(clock);
+    let den = 4_000_000_000;
+    let cycles = (num + den / 2) / den;
+    if cycles > u128::from(u64::MAX) {
+        return Err("delay is too long".to_string());
+    }
+    let error = (cycles * den) as f64 / clock as f64
+        - (val as u128 * ns_per_unit) as f64;
+    Ok((cycles as u64, error))
+}
+
+/// Instructions waiting exactly `cycles`, to go at `addr`, and the address
+/// and count of each backward branch, for `repeats`.
+pub(crate) fn lower<'s>(cycles: u64, scratch: &[&'s str], addr: u16)
+    -> Result<(Insns<'s>, Vec<(u16, u64)>), String>
+{
+    let plan = plan(cycles, scratch.len().min(MAX_LOOPS));
+    if plan.words() > 64 {
+        return Err(format!(
+            "delay of {} cycles is too long{}",
+            cycles,
+            if scratch.len() < MAX_LOOPS {
+                "; delay_scratch needs more registers"
+            } else {
+                ""
+            },
+        ));
+    }
+
+    let mut insns = vec![];
+    for (&k, &reg) in plan.counts.iter().zip(scratch) {
+        insns.push((desc("movlw"), vec![OpdSrc::Const((k & 0xFF) as i64)]));
+        insns.push((desc("movwf"), vec![OpdSrc::Expr(reg)]));
+    }
+    let mut repeats = vec![];
+    for (i, (&k, &reg)) in
+        plan.counts.iter().zip(scratch).enumerate().rev()
+    {
+        // Each loop goes back to loading the counter of the one inside it.
+        let top = addr + 2 * (i as u16 + 1);
+        insns.push((
+            desc("decfsz"),
+            vec![OpdSrc::Expr(reg), OpdSrc::Dest(Some("F"))],
+        ));
+        let at = addr + insns.len() as u16;
+        insns.push(branch_back(top, at));
+        repeats.push((at, k - 1));
+    }
+    for _ in 0..plan.pad / 2 {
+        let next = addr + insns.len() as u16 + 1;
+        insns.push((desc("bra"), vec![OpdSrc::Const(i64::from(next))]));
+    }
+    if plan.pad % 2 == 1 {
+        insns.push((desc("nop"), vec![]));
+    }
+    Ok((insns, repeats))
+}
+
+#[cfg(test)]
+#[test]
+fn test_plan() {
+    assert_eq!(plan(3, 3), Plan { counts: vec![], pad: 3 });
+    assert_eq!(plan(100, 3), Plan { counts: vec![33], pad: 0 });
+    assert_eq!(plan(100, 0), Plan { counts: vec![], pad: 100 });
+    for &cycles in &[10, 769, 770, 5000, 123_457, 2_000_000] {
+        let plan = plan(cycles, 3);
+        assert_eq!(loop_cycles(&plan.counts) + plan.pad, cycles);
+        assert!(plan.words() <= 14, "{} cycles: {:?}", cycles, plan);
+    }
+
+    assert_eq!(cycles(250, "us", Some(32_000_000)), Ok((2000, 0.0)));
+    assert_eq!(cycles(1, "us", Some(10_000_000)), Ok((3, 200.0)));
+    assert!(cycles(1, "ms", None).is_err());
+
+    let (insns, repeats) = lower(100, &["d1", "d2"], 0x10).unwrap();
+    let ms: Vec<_> = insns.iter().map(|&(desc, _)| desc.mnemonic).collect();
+    assert_eq!(ms, vec!["movlw", "movwf", "decfsz", "bra"]);
+    assert_eq!(repeats, vec![(0x13, 32)]);
+    assert!(lower(1_000_000, &[], 0).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_delay() {
+    let tr_unit = ::build_tr_unit("\
+clock 32 MHz
+delay_scratch 0x70, 0x71
+start:
+    delay 250us
+end:
+    delay_cycles 5
+done:
+    return
+assert cycles(start, end) == 2000
+assert cycles(end, done) == 5
+", "test.asm").unwrap();
+    assert_eq!(tr_unit.delays, vec![(4, 2000, Some(0.0)), (6, 5, None)]);
+
+    assert!(::build_tr_unit("    delay 1 ms\n", "test.asm").unwrap_err()
+        .starts_with("line 1: "));
+}
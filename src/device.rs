@@ -0,0 +1,261 @@
+use std::fmt;
+
+/// Everything the assembler needs to know about one part.
+#[derive(Clone, Copy)]
+pub(crate) struct Device {
+    pub(crate) name: &'static str,
+    /// Processor ID for COFF, as gputils numbers them
+    pub(crate) coff_id: u32,
+    pub(crate) config: &'static [ConfigWordDesc],
+    pub(crate) eeprom_size: usize,
+    /// Banked GPR ranges (start, end), not counting common RAM.
+    pub(crate) gpr: &'static [(u16, u16)],
+    /// Common RAM (start, end) as seen from bank 0.
+    pub(crate) common: (u16, u16),
+    /// SFRs the lints care about, besides the core ones.
+    pub(crate) sfrs: &'static [Sfr],
+}
+
+/// A special function register, for the lints.
+pub(crate) struct Sfr {
+    pub(crate) name: &'static str,
+    /// Banked address; the core registers are in every bank.
+    pub(crate) addr: u16,
+    /// No bit in it can be written.
+    pub(crate) read_only: bool,
+    /// For a port, the output latch to write instead.
+    pub(crate) latch: Option<&'static str>,
+}
+
+pub(crate) struct ConfigWordDesc {
+    pub(crate) addr: u16,
+    /// Erased value; unimplemented bits read as 1.
+    pub(crate) default: u16,
+    pub(crate) fields: &'static [ConfigFieldDesc],
+}
+
+pub(crate) struct ConfigFieldDesc {
+    pub(crate) name: &'static str,
+    pub(crate) mask: u16,
+    /// (setting name, field value before shifting into place)
+    pub(crate) values: &'static [(&'static str, u16)],
+}
+
+impl Device {
+    pub(crate) fn find(name: &str) -> Option<&'static Device> {
+        DEVICES.iter().find(|dev| dev.name.eq_ignore_ascii_case(name))
+    }
+
+    pub(crate) fn config_field(&self, name: &str)
+        -> Option<(&'static ConfigWordDesc, &'static ConfigFieldDesc)>
+    {
+        for word in self.config {
+            for field in word.fields {
+                if field.name == name {
+                    return Some((word, field));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The SFR at banked address `addr`: a core register, or else one of
+/// `device`'s.
+pub(crate) fn sfr(device: Option<&Device>, addr: u16)
+    -> Option<&'static Sfr>
+{
+    CORE_SFRS.iter().find(|sfr| sfr.addr == addr & 0x7F)
+        .or_else(|| device?.sfrs.iter().find(|sfr| sfr.addr == addr))
+}
+
+impl fmt::Debug for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl ConfigFieldDesc {
+    /// Returns `word` with this field set to the named setting.
+    pub(crate) fn apply(&self, word: u16, setting: &str) -> Option<u16> {
+        let &(_, value) = self.values.iter().find(|&&(s, _)| s == setting)?;
+        let shifted = value << self.mask.trailing_zeros();
+        Some(word & !self.mask | shifted & self.mask)
+    }
+}
+
+static OFF_ON: &[(&str, u16)] = &[("OFF", 0), ("ON", 1)];
+static ON_OFF: &[(&str, u16)] = &[("ON", 0), ("OFF", 1)];
+
+static PIC16F1829_CONFIG: &[ConfigWordDesc] = &[
+    ConfigWordDesc {
+        addr: 0x8007,
+        default: 0x3FFF,
+        fields: &[
+            ConfigFieldDesc {
+                name: "FOSC",
+                mask: 0b00_0000_0000_0111,
+                values: &[
+                    ("LP", 0), ("XT", 1), ("HS", 2), ("EXTRC", 3),
+                    ("INTOSC", 4), ("ECL", 5), ("ECM", 6), ("ECH", 7),
+                ],
+            },
+            ConfigFieldDesc {
+                name: "WDTE",
+                mask: 0b00_0000_0001_1000,
+                values: &[("OFF", 0), ("SWDTEN", 1), ("NSLEEP", 2), ("ON", 3)],
+            },
+            ConfigFieldDesc {
+                name: "PWRTE",
+                mask: 0b00_0000_0010_0000,
+                values: ON_OFF,
+            },
+            ConfigFieldDesc {
+                name: "MCLRE",
+                mask: 0b00_0000_0100_0000,
+                values: OFF_ON,
+            },
+            ConfigFieldDesc {
+                name: "CP",
+                mask: 0b00_0000_1000_0000,
+                values: ON_OFF,
+            },
+            ConfigFieldDesc {
+                name: "CPD",
+                mask: 0b00_0001_0000_0000,
+                values: ON_OFF,
+            },
+            ConfigFieldDesc {
+                name: "BOREN",
+                mask: 0b00_0110_0000_0000,
+                values: &[("OFF", 0), ("SBODEN", 1), ("NSLEEP", 2), ("ON", 3)],
+            },
+            ConfigFieldDesc {
+                name: "CLKOUTEN",
+                mask: 0b00_1000_0000_0000,
+                values: ON_OFF,
+            },
+            ConfigFieldDesc {
+                name: "IESO",
+                mask: 0b01_0000_0000_0000,
+                values: OFF_ON,
+            },
+            ConfigFieldDesc {
+                name: "FCMEN",
+                mask: 0b10_0000_0000_0000,
+                values: OFF_ON,
+            },
+        ],
+    },
+    ConfigWordDesc {
+        addr: 0x8008,
+        default: 0x3FFF,
+        fields: &[
+            ConfigFieldDesc {
+                name: "WRT",
+                mask: 0b00_0000_0000_0011,
+                values: &[("ALL", 0), ("HALF", 1), ("BOOT", 2), ("OFF", 3)],
+            },
+            ConfigFieldDesc {
+                name: "PLLEN",
+                mask: 0b00_0001_0000_0000,
+                values: OFF_ON,
+            },
+            ConfigFieldDesc {
+                name: "STVREN",
+                mask: 0b00_0010_0000_0000,
+                values: OFF_ON,
+            },
+            ConfigFieldDesc {
+                name: "BORV",
+                mask: 0b00_0100_0000_0000,
+                values: &[("HI", 0), ("LO", 1)],
+            },
+            ConfigFieldDesc {
+                name: "DEBUG",
+                mask: 0b01_0000_0000_0000,
+                values: ON_OFF,
+            },
+            ConfigFieldDesc {
+                name: "LVP",
+                mask: 0b10_0000_0000_0000,
+                values: OFF_ON,
+            },
+        ],
+    },
+];
+
+const fn reg(name: &'static str, addr: u16) -> Sfr {
+    Sfr { name, addr, read_only: false, latch: None }
+}
+
+const fn read_only(name: &'static str, addr: u16) -> Sfr {
+    Sfr { name, addr, read_only: true, latch: None }
+}
+
+const fn port(name: &'static str, addr: u16, latch: &'static str) -> Sfr {
+    Sfr { name, addr, read_only: false, latch: Some(latch) }
+}
+
+static CORE_SFRS: &[Sfr] = &[
+    reg("INDF0", 0x00), reg("INDF1", 0x01), reg("PCL", 0x02),
+    reg("STATUS", 0x03), reg("FSR0L", 0x04), reg("FSR0H", 0x05),
+    reg("FSR1L", 0x06), reg("FSR1H", 0x07), reg("BSR", 0x08),
+    reg("WREG", 0x09), reg("PCLATH", 0x0A), reg("INTCON", 0x0B),
+];
+
+static PIC16F1829_SFRS: &[Sfr] = &[
+    port("PORTA", 0x00C, "LATA"), port("PORTB", 0x00D, "LATB"),
+    port("PORTC", 0x00E, "LATC"),
+    reg("TRISA", 0x08C), reg("TRISB", 0x08D), reg("TRISC", 0x08E),
+    read_only("OSCSTAT", 0x09A),
+    reg("LATA", 0x10C), reg("LATB", 0x10D), reg("LATC", 0x10E),
+    read_only("CMOUT", 0x115),
+    reg("ANSELA", 0x18C), reg("ANSELB", 0x18D), reg("ANSELC", 0x18E),
+    read_only("RCREG", 0x199),
+];
+
+static PIC16F1829_GPR: &[(u16, u16)] = &[
+    (0x020, 0x070), (0x0A0, 0x0F0), (0x120, 0x170), (0x1A0, 0x1F0),
+    (0x220, 0x270), (0x2A0, 0x2F0), (0x320, 0x370), (0x3A0, 0x3F0),
+    (0x420, 0x470), (0x4A0, 0x4F0), (0x520, 0x570), (0x5A0, 0x5F0),
+    (0x620, 0x650),
+];
+
+pub(crate) static DEVICES: &[Device] = &[
+    Device {
+        name: "PIC16F1829",
+        coff_id: 0xF1829,
+        config: PIC16F1829_CONFIG,
+        eeprom_size: 256,
+        gpr: PIC16F1829_GPR,
+        common: (0x070, 0x080),
+        sfrs: PIC16F1829_SFRS,
+    },
+];
+
+#[cfg(test)]
+#[test]
+fn test_config_field() {
+    let dev = Device::find("pic16f1829").unwrap();
+    let (word, fosc) = dev.config_field("FOSC").unwrap();
+    assert_eq!(word.addr, 0x8007);
+    assert_eq!(fosc.apply(0x3FFF, "INTOSC"), Some(0x3FFC));
+    let (word, lvp) = dev.config_field("LVP").unwrap();
+    assert_eq!(word.addr, 0x8008);
+    assert_eq!(lvp.apply(0x3FFF, "OFF"), Some(0x1FFF));
+    assert_eq!(lvp.apply(0x3FFF, "MAYBE"), None);
+    assert!(dev.config_field("NOPE").is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn test_sfr() {
+    let dev = Device::find("pic16f1829");
+    assert_eq!(sfr(dev, 0x00C).unwrap().latch, Some("LATA"));
+    assert_eq!(sfr(dev, 0x10C).unwrap().name, "LATA");
+    assert_eq!(sfr(dev, 0x283).unwrap().name, "STATUS");
+    assert_eq!(sfr(None, 0x003).unwrap().name, "STATUS");
+    assert!(sfr(None, 0x00C).is_none());
+    assert!(sfr(dev, 0x199).unwrap().read_only);
+}
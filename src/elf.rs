@@ -0,0 +1,377 @@
+//! ELF32 (little-endian) output. Program memory goes in `.text` with each
+//! 14-bit word stored as two bytes, so byte address = word address * 2, the
+//! same convention as Intel HEX. User IDs, config words and data EEPROM go
+//! in `.user_id`, `.config` and `.eeprom` at their HEX addresses, EEPROM
+//! bytes taking a word each as they do there. Each of those sections gets
+//! a `PT_LOAD`. Labels become `.symtab` entries and the statement spans
+//! become a DWARF 2 `.debug_line` program.
+//!
+//! There's no registered `e_machine` for PIC14, so we use `EM_NONE`.
+
+use TrUnit;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHF_ALLOC: u32 = 0x2;
+const SHF_EXECINSTR: u32 = 0x4;
+
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+const SHDR_SIZE: usize = 40;
+const SYM_SIZE: usize = 16;
+
+fn push_u16(buf: &mut Vec<u8>, x: u16) {
+    buf.extend_from_slice(&[x as u8, (x >> 8) as u8]);
+}
+
+fn push_u32(buf: &mut Vec<u8>, x: u32) {
+    push_u16(buf, x as u16);
+    push_u16(buf, (x >> 16) as u16);
+}
+
+fn push_uleb(buf: &mut Vec<u8>, mut x: u64) {
+    loop {
+        let byte = (x & 0x7F) as u8;
+        x >>= 7;
+        if x == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn push_sleb(buf: &mut Vec<u8>, mut x: i64) {
+    loop {
+        let byte = (x & 0x7F) as u8;
+        x >>= 7;
+        if (x == 0 && byte & 0x40 == 0) || (x == -1 && byte & 0x40 != 0) {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Appends `s` and a NUL to a string table, returning its offset.
+fn push_str(tab: &mut Vec<u8>, s: &str) -> u32 {
+    let offset = tab.len() as u32;
+    tab.extend_from_slice(s.as_bytes());
+    tab.push(0);
+    offset
+}
+
+fn debug_line(tr_unit: &TrUnit, path: &str) -> Vec<u8> {
+    const DW_LNS_COPY: u8 = 1;
+    const DW_LNS_ADVANCE_PC: u8 = 2;
+    const DW_LNS_ADVANCE_LINE: u8 = 3;
+    const DW_LNS_SET_COLUMN: u8 = 5;
+    const DW_LNE_END_SEQUENCE: u8 = 1;
+    const DW_LNE_SET_ADDRESS: u8 = 2;
+
+    let mut header = vec![
+        2, // minimum_instruction_length
+        1, // default_is_stmt
+        -5i8 as u8, // line_base
+        14, // line_range
+        10, // opcode_base: DWARF 2 has 9 standard opcodes
+    ];
+    header.extend_from_slice(&[0, 1, 1, 1, 1, 0, 0, 0, 1]);
+    header.push(0); // no include_directories
+    push_str(&mut header, path);
+    header.extend_from_slice(&[0, 0, 0]); // dir, mtime, length
+    header.push(0); // end of file_names
+
+    let mut prog = vec![0, 5, DW_LNE_SET_ADDRESS];
+    push_u32(&mut prog, 0);
+    let mut addr = 0;
+    let mut line = 1;
+    for stmt in tr_unit.stmts() {
+        if stmt.addr != addr {
+            prog.push(DW_LNS_ADVANCE_PC);
+            push_uleb(&mut prog, u64::from(stmt.addr - addr));
+            addr = stmt.addr;
+        }
+        if stmt.span.line != line {
+            prog.push(DW_LNS_ADVANCE_LINE);
+            push_sleb(&mut prog, stmt.span.line as i64 - line as i64);
+            line = stmt.span.line;
+        }
+        prog.push(DW_LNS_SET_COLUMN);
+        push_uleb(&mut prog, stmt.span.col as u64);
+        prog.push(DW_LNS_COPY);
+    }
+    prog.push(DW_LNS_ADVANCE_PC);
+    push_uleb(&mut prog, u64::from(tr_unit.end_addr() - addr));
+    prog.extend_from_slice(&[0, 1, DW_LNE_END_SEQUENCE]);
+
+    let mut out = vec![];
+    push_u32(&mut out, (2 + 4 + header.len() + prog.len()) as u32);
+    push_u16(&mut out, 2); // version
+    push_u32(&mut out, header.len() as u32);
+    out.extend(header);
+    out.extend(prog);
+    out
+}
+
+struct Section {
+    name: &'static str,
+    kind: u32,
+    flags: u32,
+    /// Byte address, for `SHF_ALLOC` sections
+    addr: u32,
+    link: u32,
+    info: u32,
+    entsize: u32,
+    data: Vec<u8>,
+}
+
+/// Sections for the words outside program memory (see `Image::sections`).
+fn image_sections(tr_unit: &TrUnit) -> Vec<Section> {
+    tr_unit.image().sections().into_iter()
+        .map(|(name, addr, words)| {
+            let mut data = vec![];
+            for word in words {
+                push_u16(&mut data, word);
+            }
+            Section {
+                name,
+                kind: SHT_PROGBITS,
+                flags: SHF_ALLOC,
+                addr: u32::from(addr) * 2,
+                link: 0,
+                info: 0,
+                entsize: 0,
+                data,
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn elf(tr_unit: &TrUnit, path: &str) -> Vec<u8> {
+    let mut text = vec![];
+    for stmt in tr_unit.stmts() {
+        push_u16(&mut text, stmt.insn.encode());
+    }
+
+    let mut strtab = vec![0];
+    let mut symtab = vec![0; SYM_SIZE]; // STN_UNDEF
+    for (name, addr) in tr_unit.labels() {
+        push_u32(&mut symtab, push_str(&mut strtab, &name));
+        push_u32(&mut symtab, u32::from(addr) * 2); // st_value
+        push_u32(&mut symtab, 0); // st_size
+        symtab.push(0x10); // STB_GLOBAL, STT_NOTYPE
+        symtab.push(0); // st_other
+        push_u16(&mut symtab, 1); // st_shndx: .text
+    }
+
+    // .symtab links to .strtab by index, so these have to come first
+    let mut sections = vec![
+        Section {
+            name: ".text",
+            kind: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            addr: 0,
+            link: 0,
+            info: 0,
+            entsize: 0,
+            data: text,
+        },
+        Section {
+            name: ".symtab",
+            kind: SHT_SYMTAB,
+            flags: 0,
+            addr: 0,
+            link: 3, // .strtab
+            info: 1, // everything after STN_UNDEF is global
+            entsize: SYM_SIZE as u32,
+            data: symtab,
+        },
+        Section {
+            name: ".strtab",
+            kind: SHT_STRTAB,
+            flags: 0,
+            addr: 0,
+            link: 0,
+            info: 0,
+            entsize: 0,
+            data: strtab,
+        },
+        Section {
+            name: ".debug_line",
+            kind: SHT_PROGBITS,
+            flags: 0,
+            addr: 0,
+            link: 0,
+            info: 0,
+            entsize: 0,
+            data: debug_line(tr_unit, path),
+        },
+    ];
+    sections.extend(image_sections(tr_unit));
+    let phnum = sections.iter()
+        .filter(|sec| sec.flags & SHF_ALLOC != 0)
+        .count();
+
+    let mut shstrtab = vec![0];
+    let names: Vec<_> = sections.iter()
+        .map(|sec| push_str(&mut shstrtab, sec.name))
+        .collect();
+    let shstrtab_name = push_str(&mut shstrtab, ".shstrtab");
+
+    // Lay out the section contents after the headers, 4-byte aligned.
+    let mut offset = EHDR_SIZE + PHDR_SIZE * phnum;
+    let mut offsets = vec![];
+    for data in sections.iter().map(|sec| &sec.data).chain(Some(&shstrtab)) {
+        offsets.push(offset);
+        offset = (offset + data.len() + 3) & !3;
+    }
+    let shoff = offset;
+    let shnum = sections.len() + 2; // null and .shstrtab
+
+    let mut out = vec![];
+
+    // ELF header
+    out.extend_from_slice(b"\x7FELF");
+    out.extend_from_slice(&[1, 1, 1, 0]); // 32-bit, LE, version 1, SysV
+    out.extend_from_slice(&[0; 8]);
+    push_u16(&mut out, 2); // ET_EXEC
+    push_u16(&mut out, 0); // EM_NONE
+    push_u32(&mut out, 1); // e_version
+    push_u32(&mut out, 0); // e_entry: reset vector
+    push_u32(&mut out, EHDR_SIZE as u32); // e_phoff
+    push_u32(&mut out, shoff as u32);
+    push_u32(&mut out, 0); // e_flags
+    push_u16(&mut out, EHDR_SIZE as u16);
+    push_u16(&mut out, PHDR_SIZE as u16);
+    push_u16(&mut out, phnum as u16);
+    push_u16(&mut out, SHDR_SIZE as u16);
+    push_u16(&mut out, shnum as u16);
+    push_u16(&mut out, (shnum - 1) as u16); // e_shstrndx
+
+    // PT_LOAD for each section that ends up on the chip
+    for (sec, &offset) in sections.iter().zip(&offsets) {
+        if sec.flags & SHF_ALLOC == 0 {
+            continue;
+        }
+        let len = sec.data.len() as u32;
+        push_u32(&mut out, 1); // PT_LOAD
+        push_u32(&mut out, offset as u32);
+        push_u32(&mut out, sec.addr); // p_vaddr
+        push_u32(&mut out, sec.addr); // p_paddr
+        push_u32(&mut out, len); // p_filesz
+        push_u32(&mut out, len); // p_memsz
+        // PF_R, and PF_X for code
+        push_u32(&mut out, if sec.flags & SHF_EXECINSTR != 0 { 5 } else { 4 });
+        push_u32(&mut out, 2); // p_align
+    }
+
+    for (data, &offset) in
+        sections.iter().map(|sec| &sec.data).chain(Some(&shstrtab))
+            .zip(&offsets)
+    {
+        out.resize(offset, 0);
+        out.extend_from_slice(data);
+    }
+    out.resize(shoff, 0);
+
+    // section headers
+    out.extend_from_slice(&[0; SHDR_SIZE]);
+    for ((sec, &name), &offset) in sections.iter().zip(&names).zip(&offsets) {
+        push_u32(&mut out, name);
+        push_u32(&mut out, sec.kind);
+        push_u32(&mut out, sec.flags);
+        push_u32(&mut out, sec.addr);
+        push_u32(&mut out, offset as u32);
+        push_u32(&mut out, sec.data.len() as u32);
+        push_u32(&mut out, sec.link);
+        push_u32(&mut out, sec.info);
+        push_u32(&mut out, if sec.kind == SHT_SYMTAB { 4 } else { 1 });
+        push_u32(&mut out, sec.entsize);
+    }
+    push_u32(&mut out, shstrtab_name);
+    push_u32(&mut out, SHT_STRTAB);
+    out.extend_from_slice(&[0; 8]); // sh_flags, sh_addr
+    push_u32(&mut out, offsets[sections.len()] as u32);
+    push_u32(&mut out, shstrtab.len() as u32);
+    out.extend_from_slice(&[0; 8]); // sh_link, sh_info
+    push_u32(&mut out, 1);
+    push_u32(&mut out, 0);
+
+    out
+}
+
+#[cfg(test)]
+#[test]
+fn test_elf() {
+    fn u16_at(buf: &[u8], i: usize) -> usize {
+        buf[i] as usize | (buf[i + 1] as usize) << 8
+    }
+    fn u32_at(buf: &[u8], i: usize) -> usize {
+        u16_at(buf, i) | u16_at(buf, i + 2) << 16
+    }
+    fn cstr_at(buf: &[u8], i: usize) -> &str {
+        let len = buf[i..].iter().position(|&b| b == 0).unwrap();
+        ::std::str::from_utf8(&buf[i..i + len]).unwrap()
+    }
+    /// (name, sh_addr, contents) of each section after the null one
+    fn sections(elf: &[u8]) -> Vec<(&str, usize, &[u8])> {
+        let shoff = u32_at(elf, 32);
+        let shdr = |i: usize| &elf[shoff + i * SHDR_SIZE..][..SHDR_SIZE];
+        let shstrtab_off = u32_at(shdr(u16_at(elf, 50)), 16);
+        (1..u16_at(elf, 48))
+            .map(&shdr)
+            .map(|sh| (
+                cstr_at(elf, shstrtab_off + u32_at(sh, 0)),
+                u32_at(sh, 12),
+                &elf[u32_at(sh, 16)..][..u32_at(sh, 20)],
+            ))
+            .collect()
+    }
+
+    let input = "start: movlw 0\n\nnext: movlw 0\n";
+    let tr_unit = ::build_tr_unit(input, "test.asm").unwrap();
+
+    let elf = elf(&tr_unit, "a.asm");
+    assert_eq!(&elf[..4], b"\x7FELF");
+    assert_eq!(u16_at(&elf, 44), 1); // e_phnum
+    let secs = sections(&elf);
+    assert_eq!(secs.len(), 5);
+    let section = |name: &str| {
+        secs.iter().find(|sec| sec.0 == name).unwrap().2
+    };
+
+    assert_eq!(section(".text"), &[0x00, 0x30, 0x00, 0x30]);
+
+    let symtab = section(".symtab");
+    let strtab = section(".strtab");
+    let syms: Vec<_> = symtab.chunks(SYM_SIZE).skip(1)
+        .map(|sym| (cstr_at(strtab, u32_at(sym, 0)), u32_at(sym, 4)))
+        .collect();
+    assert_eq!(syms, vec![("next", 2), ("start", 0)]);
+
+    let debug_line = section(".debug_line");
+    assert_eq!(u32_at(debug_line, 0) + 4, debug_line.len());
+    assert_eq!(u16_at(debug_line, 4), 2);
+    assert_eq!(debug_line[14], 10); // opcode_base
+
+    let tr_unit = ::build_tr_unit("\
+device PIC16F1829
+config 0x8007, 0x3FFC
+config 0x8008, 0x1FFF
+idlocs 1, 2
+de 0x42
+    nop
+", "test.asm").unwrap();
+    let elf = self::elf(&tr_unit, "a.asm");
+    assert_eq!(u16_at(&elf, 44), 4); // e_phnum
+    let image: Vec<_> = sections(&elf).into_iter()
+        .filter(|sec| sec.1 != 0)
+        .collect();
+    assert_eq!(image, vec![
+        (".user_id", 0x10000, &[1, 0, 2, 0][..]),
+        (".config", 0x1000E, &[0xFC, 0x3F, 0xFF, 0x1F][..]),
+        (".eeprom", 0x1E000, &[0x42, 0][..]),
+    ]);
+}
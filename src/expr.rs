@@ -0,0 +1,662 @@
+//! Operand expressions. The grammar has already checked the syntax by the
+//! time we get here, so this only needs to pick the text apart again.
+//!
+//! Values are 64-bit signed integers. Overflow, division or remainder by
+//! zero, and shifting by a negative amount or by 64 or more are errors.
+//! Division truncates toward zero and `%` takes the sign of the dividend,
+//! as in C. `>>` is arithmetic. Comparisons and `!`, `&&` and `||` give
+//! either 0 or 1. `&&`, `||` and `?:` don't evaluate the side they skip, so
+//! nothing there can cause an error.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::str::Chars;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+use unicode_xid::UnicodeXID;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Token<'s> {
+    Uint(u32),
+    Ident(&'s str),
+    Op(&'s str),
+    /// `$`
+    Here,
+}
+
+/// What an expression can refer to. Directives are evaluated while
+/// parsing, so they only get the constants from `equ` and included
+/// headers.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Symbols<'s> {
+    pub(crate) values: BTreeMap<&'s str, i64>,
+    /// Sizes of data sections, variables and labelled tables, for `sizeof`
+    pub(crate) sizes: BTreeMap<&'s str, i64>,
+    /// Address of the current instruction, for `$`
+    pub(crate) here: Option<i64>,
+    /// Local labels by (global label, name)
+    pub(crate) locals: BTreeMap<(&'s str, &'s str), i64>,
+    /// The global label in effect, for local labels
+    pub(crate) scope: Option<&'s str>,
+    /// Addresses of each anonymous label, in order
+    pub(crate) anon: BTreeMap<&'s str, Vec<i64>>,
+}
+
+impl<'s> Symbols<'s> {
+    fn lookup(&self, name: &str) -> Result<i64, String> {
+        let undefined = || format!("undefined symbol '{}'", name);
+        if name.starts_with('.') {
+            let scope = self.scope.ok_or_else(undefined)?;
+            return self.locals.get(&(scope, name)).cloned()
+                .ok_or_else(undefined);
+        }
+        if name.starts_with(|c: char| c.is_ascii_digit()) {
+            let (label, dir) = name.split_at(name.len() - 1);
+            let here = self.here.ok_or_else(undefined)?;
+            let addrs = self.anon.get(label).map_or(&[][..], |v| &v[..]);
+            let found = if dir == "b" {
+                addrs.iter().rev().find(|&&addr| addr <= here)
+            } else {
+                addrs.iter().find(|&&addr| addr > here)
+            };
+            return found.cloned().ok_or_else(|| format!(
+                "no anonymous label '{}' {}",
+                label,
+                if dir == "b" { "before here" } else { "after here" },
+            ));
+        }
+        self.values.get(name).cloned().ok_or_else(undefined)
+    }
+}
+
+pub(crate) fn is_ident_initial(c: char) -> bool {
+    c == '_' || UnicodeXID::is_xid_start(c)
+}
+
+fn is_ident_char(c: char) -> bool {
+    UnicodeXID::is_xid_continue(c)
+}
+
+/// Default_Ignorable_Code_Point, from Unicode's DerivedCoreProperties.txt
+static DEFAULT_IGNORABLE: &[(char, char)] = &[
+    ('\u{AD}', '\u{AD}'), ('\u{34F}', '\u{34F}'), ('\u{61C}', '\u{61C}'),
+    ('\u{115F}', '\u{1160}'), ('\u{17B4}', '\u{17B5}'),
+    ('\u{180B}', '\u{180F}'), ('\u{200B}', '\u{200F}'),
+    ('\u{202A}', '\u{202E}'), ('\u{2060}', '\u{206F}'),
+    ('\u{3164}', '\u{3164}'), ('\u{FE00}', '\u{FE0F}'),
+    ('\u{FEFF}', '\u{FEFF}'), ('\u{FFA0}', '\u{FFA0}'),
+    ('\u{FFF0}', '\u{FFF8}'), ('\u{1BCA0}', '\u{1BCA3}'),
+    ('\u{1D173}', '\u{1D17A}'), ('\u{E0000}', '\u{E0FFF}'),
+];
+
+/// Characters that are normally invisible, like joiners and variation
+/// selectors. Some are XID_Continue, but two names differing only in them
+/// would look the same.
+fn is_default_ignorable(c: char) -> bool {
+    DEFAULT_IGNORABLE.iter().any(|&(lo, hi)| lo <= c && c <= hi)
+}
+
+/// The grammar lets any non-ASCII character into an identifier, so names
+/// it gives us have to be checked here.
+pub(crate) fn check_ident(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    if chars.next().is_some_and(is_ident_initial)
+        && chars.all(is_ident_char)
+        && !name.chars().any(is_default_ignorable)
+    {
+        Ok(())
+    } else {
+        Err(format!("'{}' isn't a valid identifier", name.escape_debug()))
+    }
+}
+
+/// `input` with its identifiers in Normalization Form C, so that ones that
+/// look the same are the same. String and character literals (which can be
+/// data) and comments are left alone.
+pub(crate) fn nfc(input: &str) -> Cow<'_, str> {
+    if is_nfc_quick(input.chars()) == IsNormalized::Yes {
+        return Cow::Borrowed(input);
+    }
+    let mut out = String::with_capacity(input.len());
+    let mut code_start = 0;
+    let mut chars = input.char_indices();
+    while let Some((start, open)) = chars.next() {
+        if !['"', '\'', '#'].contains(&open) {
+            continue;
+        }
+        out.extend(input[code_start..start].nfc());
+        // up to the closing quote, or the end of the line
+        let mut end = input.len();
+        let mut escaped = false;
+        for (i, c) in chars.by_ref() {
+            if c == '\n' {
+                end = i;
+                break;
+            } else if escaped {
+                escaped = false;
+            } else if open != '#' && c == '\\' {
+                escaped = true;
+            } else if open != '#' && c == open {
+                end = i + 1;
+                break;
+            }
+        }
+        out.push_str(&input[start..end]);
+        code_start = end;
+    }
+    out.extend(input[code_start..].nfc());
+    Cow::Owned(out)
+}
+
+fn parse_uint(s: &str) -> Result<u32, String> {
+    let digits: String = s.chars().filter(|&c| c != '_').collect();
+    let (radix, digits) = match digits.get(..2) {
+        Some("0n") => (2, &digits[2..]),
+        Some("0c") => (8, &digits[2..]),
+        Some("0x") => (16, &digits[2..]),
+        _ => (10, &digits[..]),
+    };
+    u32::from_str_radix(digits, radix)
+        .map_err(|_| format!("bad integer literal '{}'", s))
+}
+
+static TWO_CHAR_OPS: &[&str] =
+    &["||", "&&", "==", "!=", "<=", ">=", "<<", ">>"];
+
+pub(crate) fn tokenize<'s>(s: &'s str) -> Result<Vec<Token<'s>>, String> {
+    let mut tokens = vec![];
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c == ' ' || c == '\t' {
+            rest = &rest[1..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            // `1b` and `1f` refer to anonymous labels.
+            let (digits, last) = word.split_at(end - 1);
+            if (last == "b" || last == "f")
+                && digits.chars().all(|c| c.is_ascii_digit())
+            {
+                tokens.push(Token::Ident(word));
+            } else {
+                tokens.push(Token::Uint(parse_uint(word)?));
+            }
+            rest = &rest[end..];
+        } else if c == '.' && rest[1..].starts_with(is_ident_initial) {
+            let end = 1 + rest[1..].find(|c: char| !is_ident_char(c))
+                .unwrap_or(rest.len() - 1);
+            tokens.push(Token::Ident(&rest[..end]));
+            rest = &rest[end..];
+        } else if is_ident_initial(c) {
+            let end = rest.find(|c: char| !is_ident_char(c))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(&rest[..end]));
+            rest = &rest[end..];
+        } else if TWO_CHAR_OPS.iter().any(|op| rest.starts_with(op)) {
+            tokens.push(Token::Op(&rest[..2]));
+            rest = &rest[2..];
+        } else if c == '\'' {
+            let mut chars = rest[1..].chars();
+            let val = match chars.next() {
+                Some('\\') => unescape_char(&mut chars),
+                Some('\'') | None => None,
+                c => c,
+            };
+            match (val, chars.next()) {
+                (Some(val), Some('\'')) =>
+                    tokens.push(Token::Uint(u32::from(val))),
+                _ => return Err("bad character literal".to_string()),
+            }
+            rest = chars.as_str();
+        } else if c == '$' {
+            tokens.push(Token::Here);
+            rest = &rest[1..];
+        } else if "|^&<>+-*/%~!?:(),".contains(c) {
+            tokens.push(Token::Op(&rest[..1]));
+            rest = &rest[1..];
+        } else {
+            return Err(format!(
+                "unexpected '{}' in expression", c.escape_debug(),
+            ));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Decodes the escape sequence after a backslash. `\xNN` gives the
+/// character with that code point.
+fn unescape_char(chars: &mut Chars) -> Option<char> {
+    Some(match chars.next()? {
+        'n' => '\n',
+        't' => '\t',
+        '0' => '\0',
+        'x' => {
+            let digits = chars.as_str().get(..2)
+                .filter(|d| d.chars().all(|c| c.is_ascii_hexdigit()))?;
+            let val = u8::from_str_radix(digits, 16).ok()?;
+            chars.nth(1);
+            char::from(val)
+        },
+        c => c,
+    })
+}
+
+/// Decodes a `str` token, quotes included.
+pub(crate) fn unescape(raw: &str) -> String {
+    let mut out = String::new();
+    let mut chars = raw[1..raw.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match unescape_char(&mut chars) {
+                Some(c) => c,
+                None => break,
+            },
+            c => c,
+        });
+    }
+    out
+}
+
+// loosest to tightest, all left-to-right
+static BINARY_OPS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["+", "-"],
+    &["<<", ">>"],
+    &["*", "/", "%"],
+];
+
+fn overflow() -> String {
+    "arithmetic overflow".to_string()
+}
+
+fn binary_op(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+    Ok(match op {
+        "||" => (lhs != 0 || rhs != 0) as i64,
+        "&&" => (lhs != 0 && rhs != 0) as i64,
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "&" => lhs & rhs,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "<" => (lhs < rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        "+" => lhs.checked_add(rhs).ok_or_else(overflow)?,
+        "-" => lhs.checked_sub(rhs).ok_or_else(overflow)?,
+        "<<" | ">>" if !(0..64).contains(&rhs) => {
+            return Err(format!("shift by {} is out of range", rhs));
+        },
+        "<<" => {
+            let val = lhs << rhs;
+            if val >> rhs != lhs {
+                return Err(overflow());
+            }
+            val
+        },
+        ">>" => lhs >> rhs,
+        "*" => lhs.checked_mul(rhs).ok_or_else(overflow)?,
+        "/" | "%" if rhs == 0 => {
+            return Err(format!("{} by zero", if op == "/" {
+                "division"
+            } else {
+                "remainder"
+            }));
+        },
+        "/" => lhs.checked_div(rhs).ok_or_else(overflow)?,
+        "%" => lhs.checked_rem(rhs).ok_or_else(overflow)?,
+        _ => unreachable!(),
+    })
+}
+
+/// Functions that only make sense in some places, like `cycles` in
+/// `assert`. Returns `None` for names it doesn't know.
+pub(crate) type ExtraFuncs<'f> =
+    &'f mut dyn FnMut(&str, &[i64]) -> Option<Result<i64, String>>;
+
+struct Evaluator<'t, 's: 't> {
+    tokens: &'t [Token<'s>],
+    pos: usize,
+    syms: &'t Symbols<'t>,
+    extra: ExtraFuncs<'t>,
+    /// How many enclosing `&&`, `||` or `?:` arms are being skipped
+    skipping: usize,
+}
+
+impl<'t, 's> Evaluator<'t, 's> {
+    fn peek(&self) -> Option<Token<'s>> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        match self.peek() {
+            Some(Token::Op(o)) if o == op => {
+                self.pos += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.eat(op) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", op))
+        }
+    }
+
+    /// Passes `res` through unless we're skipping, in which case nothing
+    /// is an error.
+    fn value(&self, res: Result<i64, String>) -> Result<i64, String> {
+        if self.skipping > 0 {
+            Ok(res.unwrap_or(0))
+        } else {
+            res
+        }
+    }
+
+    /// Parses with `f`, but only evaluates if `eval` is true.
+    fn maybe<F>(&mut self, eval: bool, f: F) -> Result<i64, String>
+        where F: FnOnce(&mut Self) -> Result<i64, String>
+    {
+        if !eval {
+            self.skipping += 1;
+        }
+        let res = f(self);
+        if !eval {
+            self.skipping -= 1;
+        }
+        res
+    }
+
+    fn ternary(&mut self) -> Result<i64, String> {
+        let cond = self.binary(0)?;
+        if !self.eat("?") {
+            return Ok(cond);
+        }
+        let then = self.maybe(cond != 0, |ev| ev.ternary())?;
+        self.expect(":")?;
+        let other = self.maybe(cond == 0, |ev| ev.ternary())?;
+        Ok(if cond != 0 { then } else { other })
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == BINARY_OPS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.peek() {
+            if !BINARY_OPS[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let eval = match op {
+                "&&" => lhs != 0,
+                "||" => lhs == 0,
+                _ => true,
+            };
+            let rhs = self.maybe(eval, |ev| ev.binary(level + 1))?;
+            lhs = self.value(binary_op(op, lhs, rhs))?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.eat("-") {
+            let val = self.primary()?;
+            self.value(val.checked_neg().ok_or_else(overflow))
+        } else if self.eat("~") {
+            Ok(!self.primary()?)
+        } else if self.eat("!") {
+            Ok((self.primary()? == 0) as i64)
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        let tok = self.peek().ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        match tok {
+            Token::Uint(n) => Ok(i64::from(n)),
+            Token::Ident("sizeof") if self.peek() == Some(Token::Op("(")) => {
+                self.pos += 1;
+                let name = match self.peek() {
+                    Some(Token::Ident(name)) => name,
+                    _ => return Err("sizeof needs a name".to_string()),
+                };
+                self.pos += 1;
+                self.expect(")")?;
+                let size = self.syms.sizes.get(name).cloned()
+                    .ok_or_else(|| format!("'{}' has no size", name));
+                self.value(size)
+            },
+            Token::Ident(name) if self.peek() == Some(Token::Op("(")) => {
+                self.pos += 1;
+                let mut args = vec![self.ternary()?];
+                while self.eat(",") {
+                    args.push(self.ternary()?);
+                }
+                self.expect(")")?;
+                let res = match (self.extra)(name, &args) {
+                    Some(res) => res,
+                    None => call(name, &args),
+                };
+                self.value(res)
+            },
+            Token::Ident(name) => {
+                let val = self.syms.lookup(name);
+                self.value(val)
+            },
+            Token::Here => {
+                let here = self.syms.here
+                    .ok_or_else(|| "$ can't be used here".to_string());
+                self.value(here)
+            },
+            Token::Op("(") => {
+                let val = self.ternary()?;
+                self.expect(")")?;
+                Ok(val)
+            },
+            Token::Op(op) => Err(format!("unexpected '{}'", op)),
+        }
+    }
+}
+
+fn call(func: &str, args: &[i64]) -> Result<i64, String> {
+    let arg = match *args {
+        [arg] => arg,
+        _ => return Err(format!("'{}' takes one argument", func)),
+    };
+    match func {
+        // bytes of an address
+        "low" => Ok(arg & 0xFF),
+        "high" => Ok((arg >> 8) & 0xFF),
+        "upper" => Ok((arg >> 16) & 0xFF),
+        // bank number of a data address, for movlb
+        "bank" => Ok(arg >> 7),
+        // PCLATH value for a program address, for movlp
+        "page" => Ok((arg >> 8) & 0x7F),
+        // GPR as seen through an FSR: the 80 bytes at 0x20-0x6F of each bank
+        // are laid end to end starting at 0x2000.
+        "linear" => {
+            let (bank, offset) = (arg >> 7, arg & 0x7F);
+            if arg < 0 || !(0x20..0x70).contains(&offset) {
+                return Err(format!("0x{:X} isn't in banked GPR", arg));
+            }
+            Ok(0x2000 + bank * 80 + offset - 0x20)
+        },
+        // program memory as seen through an FSR
+        "progaddr" => {
+            if !(0..0x8000).contains(&arg) {
+                return Err(format!("0x{:X} isn't a program address", arg));
+            }
+            Ok(0x8000 | arg)
+        },
+        _ => Err(format!("unknown function '{}'", func)),
+    }
+}
+
+/// Evaluates `s`, resolving identifiers with `syms`.
+pub(crate) fn eval(s: &str, syms: &Symbols) -> Result<i64, String> {
+    eval_with(s, syms, &mut |_, _| None)
+}
+
+/// `eval`, with some extra functions.
+pub(crate) fn eval_with(s: &str, syms: &Symbols, extra: ExtraFuncs)
+    -> Result<i64, String>
+{
+    let tokens = tokenize(s)?;
+    let mut ev = Evaluator {
+        tokens: &tokens,
+        pos: 0,
+        syms,
+        extra,
+        skipping: 0,
+    };
+    let val = ev.ternary()?;
+    match ev.peek() {
+        None => Ok(val),
+        Some(tok) => Err(format!("unexpected {:?}", tok)),
+    }
+}
+
+/// Every identifier the expression refers to, in order of appearance, not
+/// counting function names.
+pub(crate) fn idents(s: &str) -> Vec<&str> {
+    let tokens = tokenize(s).unwrap_or_default();
+    tokens.iter()
+        .zip(tokens.iter().skip(1).map(Some).chain(Some(None)))
+        .filter_map(|(tok, next)| match (*tok, next) {
+            (Token::Ident(_), Some(&Token::Op("("))) => None,
+            (Token::Ident(ident), _) => Some(ident),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[test]
+fn test_tokenize() {
+    use self::Token::*;
+
+    assert_eq!(
+        tokenize("(table + 0x1_0) << 2").unwrap(),
+        vec![
+            Op("("), Ident("table"), Op("+"), Uint(16), Op(")"), Op("<<"),
+            Uint(2),
+        ],
+    );
+    assert_eq!(
+        tokenize("0n101 * 0c17").unwrap(),
+        vec![Uint(5), Op("*"), Uint(15)],
+    );
+    assert_eq!(idents("~count - base_2"), vec!["count", "base_2"]);
+    assert_eq!(idents("linear(buf) + 1"), vec!["buf"]);
+    assert_eq!(
+        tokenize(r"'A' '\'' '\x7f' '\0' 'é'").unwrap(),
+        vec![Uint(0x41), Uint(0x27), Uint(0x7F), Uint(0), Uint(0xE9)],
+    );
+    assert_eq!(
+        tokenize("1b+.loop-10f").unwrap(),
+        vec![Ident("1b"), Op("+"), Ident(".loop"), Op("-"), Ident("10f")],
+    );
+    assert_eq!(idents("größe + 1"), vec!["größe"]);
+    assert!(tokenize("a\u{a0}b").is_err());
+    assert!(tokenize("''").is_err());
+    assert!(tokenize("'ab'").is_err());
+    assert!(tokenize(r"'\x4'").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_eval() {
+    let mut syms = Symbols::default();
+    syms.values.insert("x", 3);
+    syms.values.insert("table", 0x1234);
+    syms.sizes.insert("buf", 16);
+    syms.here = Some(0x10);
+    let lookup = &syms;
+    assert_eq!(eval("1 + 2 * 3", lookup), Ok(7));
+    assert_eq!(eval("(1 + 2) * 3", lookup), Ok(9));
+    assert_eq!(eval("1 << 4 | 1", lookup), Ok(17));
+    assert_eq!(eval("x - -x", lookup), Ok(6));
+    assert_eq!(eval("~0 & 0xFF", lookup), Ok(0xFF));
+    assert!(eval("y", lookup).is_err());
+    assert!(eval("(1", lookup).is_err());
+    assert!(eval("1 << 64", lookup).is_err());
+    assert_eq!(eval("linear(0x20)", lookup), Ok(0x2000));
+    assert_eq!(eval("linear(0x0A0 + x)", lookup), Ok(0x2053));
+    assert!(eval("linear(0x70)", lookup).is_err());
+    assert_eq!(eval("progaddr(0x100)", lookup), Ok(0x8100));
+    assert!(eval("nope(1)", lookup).is_err());
+    assert!(eval("low(1, 2)", lookup).is_err());
+    let mut sum = |name: &str, args: &[i64]| {
+        if name == "sum" { Some(Ok(args.iter().sum())) } else { None }
+    };
+    assert_eq!(eval_with("sum(1, x, low(0x102))", lookup, &mut sum), Ok(6));
+    assert_eq!(eval("low(table)", lookup), Ok(0x34));
+    assert_eq!(eval("high(table)", lookup), Ok(0x12));
+    assert_eq!(eval("upper(0x12_3456)", lookup), Ok(0x12));
+    assert_eq!(eval("bank(0x1A5)", lookup), Ok(3));
+    assert_eq!(eval("page(0x0912)", lookup), Ok(0x09));
+    assert_eq!(eval("sizeof(buf) - 1", lookup), Ok(15));
+    assert!(eval("sizeof(x)", lookup).is_err());
+    assert_eq!(eval("$ + 2", lookup), Ok(0x12));
+    assert!(eval("$", &Symbols::default()).is_err());
+
+    assert_eq!(eval("7 / 2 + 7 % 2 * 10", lookup), Ok(13));
+    assert_eq!(eval("-7 / 2", lookup), Ok(-3));
+    assert_eq!(eval("-7 % 2", lookup), Ok(-1));
+    assert!(eval("1 / (x - 3)", lookup).unwrap_err().contains("division"));
+    assert!(eval("1 % 0", lookup).unwrap_err().contains("remainder"));
+    assert!(eval("0x7FFF_FFFF * 0x7FFF_FFFF * 4", lookup).is_err());
+    assert!(eval("1 << 63", lookup).is_err());
+    assert_eq!(eval("x == 3 && 2 < 1 + 2", lookup), Ok(1));
+    assert_eq!(eval("x != 3 || !x", lookup), Ok(0));
+    assert_eq!(eval("1 | 2 == 2", lookup), Ok(1));
+    assert_eq!(eval("x > 2 ? 10 : y", lookup), Ok(10));
+    assert_eq!(eval("x >= 4 ? 1 / 0 : 0 ? 1 : 2", lookup), Ok(2));
+    assert_eq!(eval("x <= 2 && 1 / 0", lookup), Ok(0));
+    assert!(eval("x ? 1", lookup).is_err());
+    assert_eq!(unescape(r#""a\"\n""#), "a\"\n");
+    assert_eq!(unescape(r#""\x41\0'""#), "A\0'");
+    assert_eq!(eval("'a' - 'A'", lookup), Ok(0x20));
+    assert!(check_ident("счётчик").is_ok());
+    assert!(check_ident("_1").is_ok());
+    assert!(check_ident("1a").is_err());
+    assert!(check_ident("a\u{200b}").is_err());
+    assert!(check_ident("a\u{2212}b").is_err());
+    for c in ['\u{200C}', '\u{200D}', '\u{FE0F}', '\u{E0100}', '\u{180B}'] {
+        assert!(check_ident(&format!("a{}b", c)).is_err());
+    }
+    assert_eq!(nfc("cafe\u{301}"), "caf\u{e9}");
+    assert_eq!(
+        nfc("dt \"e\u{301}\\\"\", 'e\u{301}' # e\u{301}\ne\u{301}:"),
+        "dt \"e\u{301}\\\"\", 'e\u{301}' # e\u{301}\n\u{e9}:",
+    );
+
+    let mut syms = Symbols::default();
+    syms.locals.insert(("main", ".loop"), 2);
+    syms.locals.insert(("other", ".loop"), 7);
+    syms.anon.insert("1", vec![1, 4, 9]);
+    syms.scope = Some("main");
+    syms.here = Some(4);
+    assert_eq!(eval(".loop", &syms), Ok(2));
+    assert_eq!(eval("1b + 1f", &syms), Ok(4 + 9));
+    syms.scope = Some("other");
+    syms.here = Some(0);
+    assert_eq!(eval(".loop", &syms), Ok(7));
+    assert!(eval("1b", &syms).unwrap_err().contains("before"));
+    assert!(eval("2f", &syms).is_err());
+    syms.scope = None;
+    assert!(eval(".loop", &syms).is_err());
+}
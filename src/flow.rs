@@ -0,0 +1,169 @@
+//! Control-flow graph of the assembled program, decoding each word the
+//! way the CPU will, for the lints.
+//!
+//! Where control can go is over-approximated rather than guessed: an
+//! unannotated `brw`, or a write to PCL, can go to any of the next 256
+//! words. An unannotated `callw` calls nothing we know of.
+//!
+//! The program starts at the reset vector, and at the interrupt vector if
+//! a `retfie` can be reached from there. Everything else that follows
+//! control flow (`stack`, `cycles` and the lints) works from this graph.
+
+use data::{Flow, InsnDescTable};
+use TrUnit;
+
+pub(crate) const RESET_VECTOR: u16 = 0x0000;
+pub(crate) const INTERRUPT_VECTOR: u16 = 0x0004;
+
+pub(crate) struct Cfg {
+    /// Where control can go after each address, calls aside
+    pub(crate) next: Vec<Vec<u16>>,
+    /// What each address calls
+    pub(crate) calls: Vec<Vec<u16>>,
+    /// Whether each address runs on into the next one on its own, rather
+    /// than by jumping there
+    pub(crate) falls_through: Vec<bool>,
+    /// Whether each address can run at all
+    pub(crate) reachable: Vec<bool>,
+    /// The reset vector, and the interrupt one if there's a handler there
+    pub(crate) vectors: Vec<u16>,
+}
+
+impl Cfg {
+    pub(crate) fn new(tr_unit: &TrUnit) -> Self {
+        let table = InsnDescTable::new();
+        let stmts = tr_unit.stmts();
+        let end = stmts.len() as u16;
+        let mut cfg = Cfg {
+            next: vec![],
+            calls: vec![],
+            falls_through: vec![],
+            reachable: vec![false; stmts.len()],
+            vectors: vec![RESET_VECTOR],
+        };
+        for stmt in stmts {
+            let addr = stmt.addr;
+            let annotated = tr_unit.indirect_targets(addr).to_vec();
+            let anywhere_near = || (addr + 1..end.min(addr + 257)).collect();
+            if stmt.insn.desc.mnemonic == "_data_" {
+                cfg.next.push(vec![]);
+                cfg.calls.push(vec![]);
+                cfg.falls_through.push(false);
+                continue;
+            }
+            let insn = table.decode(stmt.insn.encode());
+            let computed =
+                insn.writes_f() && insn.operands[0].raw & 0x7F == 0x02;
+            let (next, calls) = match insn.desc.flow {
+                Flow::Return | Flow::Reset => (vec![], vec![]),
+                Flow::Branch => (vec![insn.target(addr)], vec![]),
+                Flow::Call => (vec![addr + 1], vec![insn.target(addr)]),
+                Flow::Skip => (vec![addr + 1, addr + 2], vec![]),
+                Flow::IndirectBranch if annotated.is_empty() =>
+                    (anywhere_near(), vec![]),
+                Flow::IndirectBranch => (annotated, vec![]),
+                Flow::IndirectCall => (vec![addr + 1], annotated),
+                Flow::Fallthrough if computed => (anywhere_near(), vec![]),
+                Flow::Fallthrough => (vec![addr + 1], vec![]),
+            };
+            let falls_through = !computed && matches!(
+                insn.desc.flow,
+                Flow::Fallthrough | Flow::Skip
+                    | Flow::Call | Flow::IndirectCall
+            );
+            cfg.next.push(next);
+            cfg.calls.push(calls);
+            cfg.falls_through.push(falls_through);
+        }
+
+        if cfg.has_isr(tr_unit) {
+            cfg.vectors.push(INTERRUPT_VECTOR);
+        }
+
+        // Calls are followed too, so only what reachable code calls is
+        // reachable.
+        let mut todo = cfg.vectors.clone();
+        while let Some(addr) = todo.pop() {
+            let i = addr as usize;
+            if addr >= end || cfg.reachable[i] {
+                continue;
+            }
+            cfg.reachable[i] = true;
+            todo.extend(&cfg.next[i]);
+            todo.extend(&cfg.calls[i]);
+        }
+        cfg
+    }
+
+    /// Where the program can start running: the vectors and everything
+    /// that reachable code calls.
+    pub(crate) fn entries(&self) -> Vec<u16> {
+        let mut entries = self.vectors.clone();
+        for (calls, &reachable) in self.calls.iter().zip(&self.reachable) {
+            if reachable {
+                entries.extend(calls);
+            }
+        }
+        entries.sort();
+        entries.dedup();
+        entries
+    }
+
+    /// Whether a `retfie` can be reached from the interrupt vector, calls
+    /// aside.
+    fn has_isr(&self, tr_unit: &TrUnit) -> bool {
+        let stmts = tr_unit.stmts();
+        let mut seen = vec![false; stmts.len()];
+        let mut todo = vec![INTERRUPT_VECTOR];
+        while let Some(addr) = todo.pop() {
+            let i = addr as usize;
+            if i >= stmts.len() || seen[i] {
+                continue;
+            }
+            if stmts[i].insn.desc.mnemonic == "retfie" {
+                return true;
+            }
+            seen[i] = true;
+            todo.extend(&self.next[i]);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_cfg() {
+    let tr_unit = ::build_tr_unit("\
+    call sub
+    bra $
+    call never # unreachable, and so is what it calls
+never:
+    return
+sub:
+    addwf 0x02, F # PCL
+    retlw 1
+    retlw 2
+", "test.asm").unwrap();
+    let cfg = Cfg::new(&tr_unit);
+    assert_eq!(cfg.next[4], vec![5, 6]);
+    assert_eq!(cfg.calls[0], vec![4]);
+    assert_eq!(cfg.calls[2], vec![3]);
+    assert_eq!(
+        cfg.falls_through,
+        vec![true, false, true, false, false, false, false],
+    );
+    assert_eq!(
+        cfg.reachable,
+        vec![true, true, false, false, true, true, true],
+    );
+    assert_eq!(cfg.entries(), vec![0, 4]);
+
+    // The interrupt vector only counts if it gets to a `retfie`.
+    for (src, vectors) in &[
+        ("    bra $\n    retfie\n", vec![0]),
+        ("    bra $\n    nop\n    nop\n    nop\n    retfie\n", vec![0, 4]),
+    ] {
+        let tr_unit = ::build_tr_unit(src, "test.asm").unwrap();
+        assert_eq!(&Cfg::new(&tr_unit).vectors, vectors);
+    }
+}
@@ -0,0 +1,78 @@
+//! Intel HEX (INHX32) output. Words are stored little-endian at byte
+//! address = word address * 2, so config word 0x8007 lands at 0x1000E and
+//! data EEPROM (word address 0xF000, one byte per word) at 0x1E000.
+
+use std::fmt::Write;
+use TrUnit;
+
+fn record(out: &mut String, addr: u16, kind: u8, data: &[u8]) {
+    let mut sum = data.len() as u8;
+    sum = sum.wrapping_add((addr >> 8) as u8).wrapping_add(addr as u8);
+    sum = sum.wrapping_add(kind);
+    write!(out, ":{:02X}{:04X}{:02X}", data.len(), addr, kind).unwrap();
+    for &b in data {
+        write!(out, "{:02X}", b).unwrap();
+        sum = sum.wrapping_add(b);
+    }
+    writeln!(out, "{:02X}", sum.wrapping_neg()).unwrap();
+}
+
+pub(crate) fn hex(tr_unit: &TrUnit) -> String {
+    // org gaps are left out, so they stay erased rather than programmed
+    let words = tr_unit.stmts().iter()
+        .filter(|stmt| !tr_unit.in_gap(stmt.addr))
+        .map(|stmt| (stmt.addr, stmt.insn.encode()))
+        .chain(tr_unit.image().words());
+
+    // Split into runs of up to 16 contiguous bytes within one 64 KiB page.
+    let mut runs: Vec<(u32, Vec<u8>)> = vec![];
+    for (addr, word) in words {
+        let byte_addr = u32::from(addr) * 2;
+        let bytes = [word as u8, (word >> 8) as u8];
+        match runs.last_mut() {
+            Some(&mut (start, ref mut data))
+                if start + data.len() as u32 == byte_addr
+                    && data.len() < 16
+                    && start >> 16 == byte_addr >> 16 =>
+            {
+                data.extend_from_slice(&bytes);
+            },
+            _ => runs.push((byte_addr, bytes.to_vec())),
+        }
+    }
+
+    let mut out = String::new();
+    let mut page = 0;
+    for (start, data) in runs {
+        if start >> 16 != page {
+            page = start >> 16;
+            record(&mut out, 0, 0x04, &[(page >> 8) as u8, page as u8]);
+        }
+        record(&mut out, start as u16, 0x00, &data);
+    }
+    record(&mut out, 0, 0x01, &[]);
+    out
+}
+
+#[cfg(test)]
+#[test]
+fn test_hex() {
+    let tr_unit = ::build_tr_unit("\
+device PIC16F1829
+config 0x8007, 0x3FFC
+config 0x8008, 0x1FFF
+de 0x42
+    movlw 0
+", "test.asm").unwrap();
+
+    assert_eq!(
+        hex(&tr_unit),
+        concat!(
+            ":020000000030CE\n",
+            ":020000040001F9\n",
+            ":04000E00FC3FFF1F95\n",
+            ":02E000004200DC\n",
+            ":00000001FF\n",
+        ),
+    );
+}
@@ -0,0 +1,56 @@
+//! Everything outside program memory that ends up in the output file. Each
+//! region is kept separately and only placed at its HEX address on output.
+
+use std::collections::BTreeMap;
+
+pub(crate) const USER_ID_ADDR: u16 = 0x8000;
+pub(crate) const USER_ID_LEN: usize = 4;
+/// Where data EEPROM lives in the HEX file's word address space.
+pub(crate) const EEPROM_ADDR: u16 = 0xF000;
+
+#[derive(Debug, Default)]
+pub(crate) struct Image {
+    pub(crate) user_id: Vec<u16>,
+    /// Config word values by address. Empty unless there was a `config`
+    /// directive, in which case every config word of the device is present.
+    pub(crate) config: BTreeMap<u16, u16>,
+    pub(crate) eeprom: Vec<u8>,
+}
+
+impl Image {
+    /// (HEX word address, value) for every word, in address order.
+    pub(crate) fn words(&self) -> Vec<(u16, u16)> {
+        let user_id = self.user_id.iter()
+            .enumerate()
+            .map(|(i, &word)| (USER_ID_ADDR + i as u16, word));
+        let config = self.config.iter().map(|(&addr, &word)| (addr, word));
+        let eeprom = self.eeprom.iter()
+            .enumerate()
+            .map(|(i, &byte)| (EEPROM_ADDR + i as u16, u16::from(byte)));
+        user_id.chain(config).chain(eeprom).collect()
+    }
+
+    /// Each contiguous run of `words` within a region, as (section name,
+    /// HEX word address, words), for the object file formats.
+    pub(crate) fn sections(&self) -> Vec<(&'static str, u16, Vec<u16>)> {
+        let mut sections: Vec<(&'static str, u16, Vec<u16>)> = vec![];
+        for (addr, word) in self.words() {
+            let name = if addr >= EEPROM_ADDR {
+                ".eeprom"
+            } else if addr >= USER_ID_ADDR + USER_ID_LEN as u16 {
+                ".config"
+            } else {
+                ".user_id"
+            };
+            match sections.last_mut() {
+                Some(&mut (last, start, ref mut words))
+                    if last == name && start + words.len() as u16 == addr =>
+                {
+                    words.push(word);
+                },
+                _ => sections.push((name, addr, vec![word])),
+            }
+        }
+        sections
+    }
+}
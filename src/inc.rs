@@ -0,0 +1,282 @@
+//! MPASM device headers (`p16f1829.inc` and friends). Their `EQU`s become
+//! constants, and `__MAXRAM`/`__BADRAM` give the device's GPR map.
+//!
+//! The big headers run to thousands of lines, so a parsed header is kept
+//! for the life of the process and also cached on disk, keyed by path and
+//! checked against the header's size and modification time.
+
+use expr::{self, Symbols};
+use mpasm;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// Bump this whenever the cache format or `parse` changes.
+const CACHE_VERSION: u32 = 2;
+
+/// Directives that can appear in a header but don't matter to us. The
+/// conditionals only ever guard a wrong-device `MESSG`.
+static IGNORED: &[&str] = &[
+    "list", "nolist", "ifdef", "ifndef", "else", "endif", "messg", "error",
+];
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Header {
+    /// `EQU` names and values, in file order
+    pub(crate) equs: Vec<(String, i64)>,
+    max_ram: Option<u16>,
+    /// Unimplemented RAM (first, last)
+    bad_ram: Vec<(u16, u16)>,
+    /// Banked GPR ranges (start, end), if the header has `__MAXRAM`
+    pub(crate) gpr: Option<Vec<(u16, u16)>>,
+}
+
+fn eval(raw: &str) -> Result<i64, String> {
+    expr::eval(&mpasm::expr(raw)?, &Symbols::default())
+}
+
+fn ram_addr(raw: &str) -> Result<u16, String> {
+    let addr = eval(raw)?;
+    if !(0..0x1000).contains(&addr) {
+        return Err(format!("RAM address {} is out of range", addr));
+    }
+    Ok(addr as u16)
+}
+
+impl Header {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut header = Header::default();
+        for (i, line) in text.lines().enumerate() {
+            let err = |msg: String| format!("line {}: {}", i + 1, msg);
+            let code = mpasm::split_comment(line).map_err(&err)?.0;
+            let mut words = code.split_whitespace();
+            let first = words.next().unwrap_or("");
+            let second = words.next().unwrap_or("");
+            if second.eq_ignore_ascii_case("equ") {
+                let raw = code.trim_start()[first.len()..].trim_start()
+                    [second.len()..].to_string();
+                let val = eval(&raw).map_err(&err)?;
+                header.equs.push((first.to_string(), val));
+            } else if first.eq_ignore_ascii_case("__maxram") {
+                header.max_ram =
+                    Some(ram_addr(&code.trim()[8..]).map_err(&err)?);
+            } else if first.eq_ignore_ascii_case("__badram") {
+                for range in code.trim()[8..].split(',') {
+                    let mut ends = range.splitn(2, '-');
+                    let first = ram_addr(ends.next().unwrap()).map_err(&err)?;
+                    let last = match ends.next() {
+                        Some(raw) => ram_addr(raw).map_err(&err)?,
+                        None => first,
+                    };
+                    header.bad_ram.push((first, last));
+                }
+            } else if !first.is_empty() && !IGNORED.iter()
+                .any(|dir| first.eq_ignore_ascii_case(dir))
+            {
+                return Err(err(format!(
+                    "'{}' isn't supported in a header", first,
+                )));
+            }
+        }
+        header.gpr = header.max_ram.map(|max| gpr(max, &header.bad_ram));
+        Ok(header)
+    }
+
+    fn to_cache(&self, stamp: &str) -> String {
+        let mut out = format!("{}\n", stamp);
+        if let Some(max) = self.max_ram {
+            out += &format!("maxram {}\n", max);
+        }
+        for &(first, last) in &self.bad_ram {
+            out += &format!("badram {} {}\n", first, last);
+        }
+        for (name, val) in &self.equs {
+            out += &format!("equ {} {}\n", name, val);
+        }
+        out
+    }
+
+    /// `None` if the cache is stale or damaged.
+    fn from_cache(text: &str, stamp: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next() != Some(stamp) {
+            return None;
+        }
+        let mut header = Header::default();
+        for line in lines {
+            let mut words = line.split(' ');
+            match (words.next()?, words.next()?, words.next()) {
+                ("maxram", max, None) => {
+                    header.max_ram = Some(max.parse().ok()?);
+                },
+                ("badram", first, Some(last)) => header.bad_ram.push(
+                    (first.parse().ok()?, last.parse().ok()?),
+                ),
+                ("equ", name, Some(val)) => header.equs.push(
+                    (name.to_string(), val.parse().ok()?),
+                ),
+                _ => return None,
+            }
+        }
+        header.gpr = header.max_ram.map(|max| gpr(max, &header.bad_ram));
+        Some(header)
+    }
+}
+
+/// The GPR part of each bank up to `max_ram`, less the bad ranges.
+fn gpr(max_ram: u16, bad_ram: &[(u16, u16)]) -> Vec<(u16, u16)> {
+    let mut ranges = vec![];
+    for bank in 0..=max_ram >> 7 {
+        let mut start = (bank << 7) + 0x20;
+        let end = (bank << 7) + 0x70;
+        let mut holes: Vec<_> = bad_ram.iter()
+            .filter(|&&(first, last)| first < end && last >= start)
+            .collect();
+        holes.sort();
+        for &&(first, last) in &holes {
+            if first > start {
+                ranges.push((start, first));
+            }
+            start = start.max(last + 1);
+        }
+        if start < end {
+            ranges.push((start, end));
+        }
+    }
+    ranges
+}
+
+/// Looks for `name` next to `including` (the source file with the
+/// `include`), then as given, then in each directory in `MYOPIC_INCLUDE`.
+fn find(name: &str, including: &Path) -> Option<PathBuf> {
+    let dirs = env::var_os("MYOPIC_INCLUDE").unwrap_or_default();
+    including.parent().map(|dir| dir.join(name))
+        .into_iter()
+        .chain(Some(PathBuf::from(name)))
+        .chain(env::split_paths(&dirs).map(|dir| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+/// `MYOPIC_CACHE`, or `myopic` in the usual per-user cache directory.
+fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("MYOPIC_CACHE") {
+        return Some(dir.into());
+    }
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| {
+            Path::new(&home).join(".cache")
+        }))
+        .map(|dir| dir.join("myopic"))
+}
+
+fn load_uncached(path: &Path) -> Result<Header, String> {
+    let err = |msg: String| format!("{}: {}", path.display(), msg);
+    let meta = fs::metadata(path).map_err(|e| err(e.to_string()))?;
+    let mtime = meta.modified().ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    let stamp = format!(
+        "myopic {} {} {} {}",
+        CACHE_VERSION, meta.len(), mtime, path.display(),
+    );
+    let cache_path = cache_dir().map(|dir| {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        dir.join(format!("{:016x}.inc", hasher.finish()))
+    });
+
+    if let Some(ref cache_path) = cache_path {
+        let cached = fs::read_to_string(cache_path).ok()
+            .and_then(|text| Header::from_cache(&text, &stamp));
+        if let Some(header) = cached {
+            return Ok(header);
+        }
+    }
+    let text = fs::read_to_string(path).map_err(|e| err(e.to_string()))?;
+    let header = Header::parse(&text).map_err(err)?;
+    if let Some(ref cache_path) = cache_path {
+        // The cache is only an optimization, so failing to write it is fine.
+        let _ = fs::create_dir_all(cache_path.parent().unwrap());
+        let _ = fs::write(cache_path, header.to_cache(&stamp));
+    }
+    Ok(header)
+}
+
+/// Finds, parses and caches header `name`, included from `including`. Each
+/// header is loaded at most once per process and never freed.
+pub(crate) fn load(name: &str, including: &str)
+    -> Result<&'static Header, String>
+{
+    static LOADED: Mutex<Vec<(PathBuf, &'static Header)>> =
+        Mutex::new(Vec::new());
+
+    let path = find(name, Path::new(including))
+        .ok_or_else(|| format!("can't find header '{}'", name))?;
+    let path = path.canonicalize().unwrap_or(path);
+    let mut loaded = LOADED.lock().unwrap();
+    if let Some(&(_, header)) = loaded.iter().find(|&(p, _)| *p == path) {
+        return Ok(header);
+    }
+    let header: &'static Header = Box::leak(Box::new(load_uncached(&path)?));
+    loaded.push((path, header));
+    Ok(header)
+}
+
+#[cfg(test)]
+#[test]
+fn test_header() {
+    let text = "\
+        LIST\n\
+; ----- STATUS Bits ---\n\
+STATUS            EQU  H'0003'\n\
+C                 EQU  H'0000'  ; carry\n\
+_CONFIG1          EQU  H'8007'\n\
+        IFNDEF __16F1829\n\
+            MESSG \"wrong header\"\n\
+        ENDIF\n\
+        __MAXRAM  H'017F'\n\
+        __BADRAM  H'0050'-H'005F', H'0120'-H'0170', H'00A0'\n\
+";
+    let header = Header::parse(text).unwrap();
+    assert_eq!(header.equs, vec![
+        ("STATUS".to_string(), 3),
+        ("C".to_string(), 0),
+        ("_CONFIG1".to_string(), 0x8007),
+    ]);
+    assert_eq!(header.gpr, Some(vec![
+        (0x020, 0x050), (0x060, 0x070), (0x0A1, 0x0F0),
+    ]));
+    assert!(Header::parse("X EQU H'0G'").is_err());
+    assert_eq!(
+        Header::parse("; ok\nX EQU 1\n  #define Y 2\n"),
+        Err("line 3: '#define' isn't supported in a header".to_string()),
+    );
+
+    let cache = header.to_cache("stamp");
+    assert_eq!(Header::from_cache(&cache, "stamp"), Some(header));
+    assert_eq!(Header::from_cache(&cache, "other stamp"), None);
+    let damaged = cache.replace("maxram 383", "maxram 0x17F");
+    assert_eq!(Header::from_cache(&damaged, "stamp"), None);
+}
+
+#[cfg(test)]
+#[test]
+fn test_find() {
+    let dir = env::temp_dir().join(format!("myopic-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub/dev.inc"), "").unwrap();
+
+    let src = dir.join("sub/main.asm");
+    assert_eq!(find("dev.inc", &src), Some(dir.join("sub/dev.inc")));
+    assert_eq!(
+        find("sub/dev.inc", &dir.join("main.asm")),
+        Some(dir.join("sub/dev.inc")),
+    );
+    assert_eq!(find("dev.inc", &dir.join("main.asm")), None);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,6 +1,16 @@
 extern crate destroy;
+extern crate unicode_normalization;
+extern crate unicode_script;
+extern crate unicode_xid;
 
-use data::{Insn, INSN_DESCS, Opd};
+use data::{Flow, Insn, InsnDesc, DATA_WORD_DESC, INSN_DESCS, Opd, OpdDescKind};
+use device::Device;
+use expr::{is_ident_initial, Symbols};
+use image::Image;
+use inc::Header;
+use ram::DataSection;
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
 use destroy::parse::{
     parse_grammar,
     ParseError,
@@ -11,7 +21,25 @@ use destroy::string_table::{
     StringTableEntry,
 };
 
+mod coff;
+mod cycles;
 mod data;
+mod debug_info;
+mod delay;
+mod device;
+mod elf;
+mod expr;
+mod flow;
+mod hex;
+mod image;
+mod inc;
+mod lint;
+mod listing;
+mod map;
+mod mpasm;
+mod pseudo;
+mod ram;
+mod stack;
 
 static GRAMMAR: &str = r##"
     dec_nzdigit = '1'..'9'
@@ -38,24 +66,37 @@ static GRAMMAR: &str = r##"
     hex_digit = dec_digit / 'a'..'f' / 'A'..'F'
     hex_uint = "0x" (hex_digit / "_")+
 
-    str =
-        "\""
-        ("\\" ("n" / "t" / "\\" / "\"") / -"\"" -"\n" %)[cp]*
-        "\""
-    ident_initial = latin_letter / "_" / 0x80..0x10FFFF # TODO
-    ident = ident_initial (ident_initial / dec_digit)* # TODO
+    escape =
+        "\\" ("n" / "t" / "0" / "\\" / "\"" / "'" / "x" hex_digit hex_digit)
+    str = "\"" (escape / -"\"" -"\n" %)[cp]* "\""
+    chr = "'" (escape / -"'" -"\n" %) "'"
+    # Non-ASCII identifiers are checked against XID_Start and XID_Continue
+    # after parsing (see `expr::check_ident`).
+    ident_initial = latin_letter / "_" / 0x80..0x10FFFF
+    ident = ident_initial (ident_initial / dec_digit)*
 
     # same as C precedence except for bit shift operators
-    expr = expr2[opd] (wso "|" wso expr2[opd])* # ltr
-    expr2 = expr3[opd] (wso "^" wso expr3[opd])* # ltr
-    expr3 = expr4[opd] (wso "&" wso expr4[opd])* # ltr
-    expr4 = expr5[opd] (wso ("+" / "-")[op] wso expr5[opd])* # ltr
-    expr5 = expr6[opd] (wso ("<<" / ">>")[op] wso expr6[opd])* # (!) ltr
-    expr6 = expr7[opd] (wso "*" wso expr7[opd])* # ltr
-    expr7 = ("-" / "~")[pre]? wso expr8[opd] # rtl
+    expr = expr2[opd] (wso "?" wso expr[opd] wso ":" wso expr[opd])? # rtl
+    expr2 = expr3[opd] (wso "||" wso expr3[opd])* # ltr
+    expr3 = expr4[opd] (wso "&&" wso expr4[opd])* # ltr
+    expr4 = expr5[opd] (wso "|" -"|" wso expr5[opd])* # ltr
+    expr5 = expr6[opd] (wso "^" wso expr6[opd])* # ltr
+    expr6 = expr7[opd] (wso "&" -"&" wso expr7[opd])* # ltr
+    expr7 = expr8[opd] (wso ("==" / "!=")[op] wso expr8[opd])* # ltr
     expr8 =
-        (bin_uint / oct_uint / hex_uint / dec_uint)[uint]
+        expr9[opd] (wso ("<=" / ">=" / "<" / ">")[op] wso expr9[opd])* # ltr
+    expr9 = expr10[opd] (wso ("+" / "-")[op] wso expr10[opd])* # ltr
+    expr10 = expr11[opd] (wso ("<<" / ">>")[op] wso expr11[opd])* # (!) ltr
+    expr11 = expr12[opd] (wso ("*" / "/" / "%")[op] wso expr12[opd])* # ltr
+    expr12 = ("-" / "~" / "!")[pre]? wso expr13[opd] # rtl
+    expr13 =
+        (dec_digit+ ("b" / "f") -(ident_initial / dec_digit))[anon]
+        / (bin_uint / oct_uint / hex_uint / dec_uint)[uint]
+        / chr[chr]
+        / ident[func] wso "(" wso expr[arg] (wso "," wso expr[arg])* wso ")"
         / ident[ident]
+        / ("." ident)[local]
+        / "$"[here]
         / "(" wso expr[inner] wso ")"
 
     mod = "++" / "--"
@@ -74,15 +115,13 @@ static GRAMMAR: &str = r##"
         # f, d
         / (
             "addwf" / "addwfc" / "andwf" / "asrf" / "lslf" / "lsrf" / "comf"
-            / "decf" / "incf" / "iorwf" / "movf" / "rlf" / "rrf" / "subwf"
-            / "subwfb" / "swapf" / "xorwf"
+            / "decfsz" / "decf" / "incfsz" / "incf" / "iorwf" / "movf"
+            / "rlf" / "rrf" / "subwf" / "subwfb" / "swapf" / "xorwf"
         )[m] wso expr[f] (wso "," wso ("W"/ "F")[d])?
 
         # f, b
-        / (
-            "decfsz" / "incfsz" / "bcf" / "bsf" / "btfsc" / "btfss" / "ifc"
-            / "ifs"
-        )[m] wso expr[f] wso "," wso expr[b]
+        / ("bcf" / "bsf" / "btfsc" / "btfss" / "ifc" / "ifs")[m]
+            wso expr[f] wso "," wso expr[b]
 
         # k
         / (
@@ -90,6 +129,17 @@ static GRAMMAR: &str = r##"
             / "xorlw" / "bra" / "call" / "goto" / "retlw"
         )[m] wso expr[k]
 
+        # pseudo-instructions
+        / "banksel"[m] wso expr[k]
+        / "lfsr"[m] wso fsrn[fsrn] wso "," wso expr[k]
+        / ("if_set" / "if_clr" / "while_set" / "while_clr")[m]
+            wso expr[f] wso "," wso expr[b]
+        / "loop_decfsz"[m] wso expr[f]
+        / "delay_cycles"[m] wso expr[k]
+        / "delay"[m] wso expr[k] wso ("ns" / "us" / "ms" / "s")[unit]
+        / ("else" / "endif" / "endw" / "endl")[m]
+        / ("dt" / "da")[m] pwso (str / expr)[k] (wso "," wso (str / expr)[k])*
+
         # tris
         / "tris"[m] wso ("TRISA" / "TRISB" / "TRISC")[t]
 
@@ -97,14 +147,651 @@ static GRAMMAR: &str = r##"
         / ("moviw" / "movwi")[m] wso
             (mod[pre] fsrn[fsrn] / fsrn[fsrn] mod[post])
 
-    line = (ident[label] wso ":" wso)? (insn wso)? comment?
+    config_setting = ident[name] wso "=" wso ident[value]
+    directive =
+        "device"[dir] pwso ident[device]
+        / "config"[dir] pwso config_setting[setting]
+            (wso "," wso config_setting[setting])*
+        / "config"[dir] pwso expr[addr] wso "," wso expr[val]
+        / "include"[dir] pwso str[path]
+        / "lint"[dir] pwso config_setting[setting]
+            (wso "," wso config_setting[setting])*
+        / "targets"[dir] pwso expr[val] (wso "," wso expr[val])*
+        / "repeats"[dir] pwso expr[val] (wso "," wso expr[val])?
+        / "assert"[dir] pwso expr[val] (wso "," wso str[msg])?
+        / "clock"[dir] pwso expr[val] wso ("MHz" / "kHz" / "Hz")[unit]
+        / "delay_scratch"[dir] pwso expr[val] (wso "," wso expr[val])*
+        / "idlocs"[dir] pwso expr[val] (wso "," wso expr[val])*
+        / "de"[dir] pwso (str / expr)[val] (wso "," wso (str / expr)[val])*
+        / ("udata_shr" / "udata")[dir] pwso ident[section]
+        / ident[var] pwso "res"[dir] pwso expr[val]
+        / ident[const] pwso "equ"[dir] pwso expr[val]
+        / "org"[dir] pwso expr[addr]
+        / "cblock"[dir] (pwso expr[addr])?
+        / "endc"[dir]
+        / cblock_var[cblock_var] (wso "," wso cblock_var[cblock_var])*
+
+    # name, or name[size], inside cblock ... endc
+    cblock_var = ident[name] (wso "[" wso expr[size] wso "]")?
+
+    label_name = "." ident / ident / dec_digit+
+    line =
+        (label_name[label] wso ":" wso)? (insn wso / directive wso)?
+        comment[comment]?
     tr_unit = ws (line[line] "\n" ws)* line[line]?
 "##;
 
+/// 1-based line and column of a statement in its source file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Span {
+    pub(crate) line: usize,
+    pub(crate) col: usize,
+}
+
+impl Span {
+    /// `part` must be a slice of `input`.
+    fn of(input: &str, part: &str) -> Self {
+        let offset = part.as_ptr() as usize - input.as_ptr() as usize;
+        let before = &input[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Span {
+            line: before.matches('\n').count() + 1,
+            col: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+/// Where an operand's value comes from, resolved once every symbol is known.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum OpdSrc<'s> {
+    Expr(&'s str),
+    /// Bank number of a data address (`banksel`)
+    BankOf(&'s str),
+    /// Bits 8 and up of an address
+    High(&'s str),
+    /// Bits 0-7 of an address
+    Low(&'s str),
+    Const(i64),
+    /// `W` or `F`; `F` if omitted
+    Dest(Option<&'s str>),
+    /// FIXME: The grammar has no syntax for this operand yet.
+    Missing,
+}
+
+impl<'s> OpdSrc<'s> {
+    /// Pairs each operand of `desc` with the grammar capture it's written in.
+    fn for_insn<C>(desc: &InsnDesc, cap: C) -> Vec<Self>
+        where C: Fn(&str) -> Option<&'s str>
+    {
+        desc.operands.iter()
+            .map(|opd| {
+                let src = match opd.kind {
+                    OpdDescKind::D => return OpdSrc::Dest(cap("d")),
+                    OpdDescKind::DC(_) => None,
+                    OpdDescKind::F => cap("f").or_else(|| cap("a")),
+                    OpdDescKind::B => cap("b"),
+                    _ => cap("k"),
+                };
+                src.map_or(OpdSrc::Missing, OpdSrc::Expr)
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug)]
-pub(crate) struct TrUnit<'s>(Vec<(Vec<&'s str>, Insn)>);
+pub(crate) struct Stmt<'s> {
+    pub(crate) labels: Vec<(&'s str, Span)>,
+    pub(crate) insn: Insn,
+    /// One per `insn.desc.operands`
+    pub(crate) opds: Vec<OpdSrc<'s>>,
+    /// Identifiers used in the operands.
+    pub(crate) refs: Vec<&'s str>,
+    pub(crate) addr: u16,
+    /// The global label that local labels here belong to
+    pub(crate) scope: Option<&'s str>,
+    pub(crate) span: Span,
+    /// Whether a pseudo-instruction or directive made this, like the
+    /// `movlb` from a `banksel`
+    pub(crate) expanded: bool,
+}
 
-pub fn parse_tr_unit(input: &str) -> Result<String, String> {
+impl<'s> Stmt<'s> {
+    /// The symbol table name for label or reference `name` in this
+    /// statement (see `symbol_name`).
+    pub(crate) fn symbol_name(&self, name: &str) -> Option<String> {
+        symbol_name(self.scope, name)
+    }
+}
+
+/// The symbol table name for label or reference `name` under global label
+/// `scope`: `.loop` under `main` is `main.loop`. Anonymous labels like `1`
+/// and references like `1b` don't have one.
+fn symbol_name(scope: Option<&str>, name: &str) -> Option<String> {
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        None
+    } else if name.starts_with('.') {
+        Some(format!("{}{}", scope.unwrap_or(""), name))
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// An `assert`, checked once everything else is known.
+#[derive(Debug)]
+struct Assert<'s> {
+    /// Address of the next instruction, for `$`
+    addr: u16,
+    scope: Option<&'s str>,
+    cond: &'s str,
+    msg: Option<String>,
+    span: Span,
+}
+
+/// An assembled program.
+#[derive(Debug, Default)]
+pub struct TrUnit<'s> {
+    /// The source, and where it came from
+    input: &'s str,
+    path: &'s str,
+    stmts: Vec<Stmt<'s>>,
+    device: Option<&'static Device>,
+    image: Image,
+    /// Config field name -> setting name, to catch conflicts.
+    config_settings: BTreeMap<&'s str, &'s str>,
+    /// Config words given as a whole number rather than by setting.
+    raw_config_words: BTreeSet<u16>,
+    data_sections: Vec<DataSection<'s>>,
+    /// Where the open `cblock` is, until its `endc`
+    cblock: Option<Span>,
+    /// `equ`s, and `EQU`s from included headers
+    constants: Symbols<'s>,
+    /// Identifiers used by directives, like `equ` and `org`, with the
+    /// global label in effect and where they're used. Instructions keep
+    /// theirs in `Stmt::refs`.
+    dir_refs: Vec<(&'s str, Option<&'s str>, Span)>,
+    /// Where each constant was defined
+    constant_defs: BTreeMap<&'s str, Span>,
+    /// GPR map from an included header, in place of the device's
+    gpr: Option<&'static [(u16, u16)]>,
+    /// `targets` annotations: (address of the `callw` or `brw`, targets,
+    /// annotation)
+    indirect: Vec<(u16, Vec<&'s str>, Span)>,
+    /// `indirect`, resolved
+    indirect_targets: BTreeMap<u16, Vec<u16>>,
+    /// `repeats` annotations: address of the backward jump -> (fewest,
+    /// most) times it's taken each time round its loop
+    repeats: BTreeMap<u16, (u64, u64)>,
+    asserts: Vec<Assert<'s>>,
+    /// Every symbol, once resolved
+    symbols: Symbols<'s>,
+    /// Oscillator frequency in Hz, for `delay`
+    clock: Option<u64>,
+    /// Loop counters for `delay`, outermost first
+    delay_scratch: Vec<&'s str>,
+    /// (line, cycles, how far off the asked-for time in ns) for each
+    /// `delay` and `delay_cycles`
+    pub(crate) delays: Vec<(usize, u64, Option<f64>)>,
+    /// Where each `dt` or `da` line starts, and how many words it has
+    tables: BTreeMap<u16, u16>,
+    /// Where each gap an `org` skips over starts, and how many words it
+    /// has. The gaps are in `stmts`, as erased words, but aren't code.
+    gaps: BTreeMap<u16, u16>,
+    /// Banked address of each instruction's register operand, as written
+    regs: BTreeMap<u16, u16>,
+    /// (line, lint, level) for each `lint` setting
+    lint_levels: Vec<(usize, &'s str, lint::Level)>,
+    /// Lints turned off by `nolint` comments, by line; none listed means
+    /// all of them
+    nolint: BTreeMap<usize, Vec<&'s str>>,
+    /// What `lint` settings and `nolint` comments leave of the lints
+    warnings: Vec<(Span, String)>,
+}
+
+impl<'s> TrUnit<'s> {
+    pub(crate) fn stmts(&self) -> &[Stmt<'s>] {
+        &self.stmts
+    }
+
+    pub(crate) fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Next free program address.
+    pub(crate) fn end_addr(&self) -> u16 {
+        self.stmts.last().map_or(0, |stmt| stmt.addr + 1)
+    }
+
+    /// Program words taken by code and data, not counting `org` gaps.
+    pub(crate) fn words_used(&self) -> usize {
+        let gaps: usize = self.gaps.values().map(|&n| n as usize).sum();
+        self.stmts.len() - gaps
+    }
+
+    /// Whether `addr` is in a gap skipped by an `org`.
+    pub(crate) fn in_gap(&self, addr: u16) -> bool {
+        self.gaps.range(..=addr).next_back()
+            .is_some_and(|(&start, &words)| addr < start + words)
+    }
+
+    /// Whether `addr` is in a `dt` or `da` table.
+    pub(crate) fn in_table(&self, addr: u16) -> bool {
+        self.tables.range(..=addr).next_back()
+            .is_some_and(|(&start, &words)| addr < start + words)
+    }
+
+    /// Every reference to a symbol, by symbol table name, in source order.
+    pub(crate) fn refs(&self) -> Vec<(String, Span)> {
+        let mut refs: Vec<_> = self.stmts.iter()
+            .flat_map(|stmt| {
+                stmt.refs.iter().filter_map(move |r| {
+                    Some((stmt.symbol_name(r)?, stmt.span))
+                })
+            })
+            .chain(self.dir_refs.iter().filter_map(|&(r, scope, span)| {
+                Some((symbol_name(scope, r)?, span))
+            }))
+            .collect();
+        refs.sort_by_key(|&(_, span)| span.line);
+        refs
+    }
+
+    /// Where the `callw` or `brw` at `addr` can go, if it's annotated.
+    pub(crate) fn indirect_targets(&self, addr: u16) -> &[u16] {
+        self.indirect_targets.get(&addr).map_or(&[], |targets| &targets[..])
+    }
+
+    /// The first label at `addr`, or else the address itself.
+    pub(crate) fn addr_name(&self, addr: u16) -> String {
+        self.stmts.get(addr as usize)
+            .and_then(|stmt| {
+                let &(label, _) = stmt.labels.first()?;
+                stmt.symbol_name(label)
+            })
+            .unwrap_or_else(|| format!("0x{:04X}", addr))
+    }
+
+    fn select_device(&mut self, name: &str) -> Result<(), String> {
+        if self.device.is_some() {
+            return Err("device already selected".to_string());
+        }
+        self.device = Some(Device::find(name)
+            .ok_or_else(|| format!("unknown device '{}'", name))?);
+        Ok(())
+    }
+
+    fn set_config(&mut self, name: &'s str, value: &'s str)
+        -> Result<(), String>
+    {
+        let dev = self.device.ok_or("config before device")?;
+        let (word, field) = dev.config_field(name).ok_or_else(|| {
+            format!("{} has no config setting '{}'", dev.name, name)
+        })?;
+
+        if self.raw_config_words.contains(&word.addr) {
+            return Err(format!(
+                "config word 0x{:04X} is already set", word.addr,
+            ));
+        }
+        let old_value = *self.config_settings.entry(name).or_insert(value);
+        if old_value != value {
+            return Err(format!("{} is already set to {}", name, old_value));
+        }
+
+        let bits = self.config_word(dev, word.addr);
+        *bits = field.apply(*bits, value)
+            .ok_or_else(|| format!("{} can't be set to {}", name, value))?;
+        Ok(())
+    }
+
+    /// Sets a whole config word, which then can't also be set by field.
+    fn set_config_word(&mut self, addr: i64, val: i64) -> Result<(), String> {
+        let dev = self.device.ok_or("config before device")?;
+        let word = dev.config.iter()
+            .find(|word| i64::from(word.addr) == addr)
+            .ok_or_else(|| {
+                format!("{} has no config word at 0x{:X}", dev.name, addr)
+            })?;
+        if !(0..=0x3FFF).contains(&val) {
+            return Err(format!("config word {} doesn't fit in 14 bits", val));
+        }
+        let set_by_field = self.config_settings.keys().find(|&&name| {
+            dev.config_field(name).unwrap().0.addr == word.addr
+        });
+        if let Some(name) = set_by_field {
+            return Err(format!(
+                "config word 0x{:04X} already has {} set", word.addr, name,
+            ));
+        }
+        if !self.raw_config_words.insert(word.addr) {
+            return Err(format!(
+                "config word 0x{:04X} is already set", word.addr,
+            ));
+        }
+        *self.config_word(dev, word.addr) = val as u16;
+        Ok(())
+    }
+
+    /// Config word `addr` in the image, filling in the device's defaults
+    /// the first time.
+    fn config_word(&mut self, dev: &Device, addr: u16) -> &mut u16 {
+        let config = &mut self.image.config;
+        if config.is_empty() {
+            for word in dev.config {
+                config.insert(word.addr, word.default);
+            }
+        }
+        config.get_mut(&addr).unwrap()
+    }
+
+    fn push_user_id(&mut self, val: i64) -> Result<(), String> {
+        if self.image.user_id.len() == image::USER_ID_LEN {
+            return Err(format!(
+                "only {} user ID words are available", image::USER_ID_LEN,
+            ));
+        }
+        if !(0..=0x3FFF).contains(&val) {
+            return Err(format!("user ID word {} doesn't fit in 14 bits", val));
+        }
+        self.image.user_id.push(val as u16);
+        Ok(())
+    }
+
+    fn push_eeprom(&mut self, val: i64) -> Result<(), String> {
+        let dev = self.device.ok_or("de before device")?;
+        if self.image.eeprom.len() == dev.eeprom_size {
+            return Err(format!(
+                "{} has only {} bytes of data EEPROM",
+                dev.name, dev.eeprom_size,
+            ));
+        }
+        if !(-0x80..=0xFF).contains(&val) {
+            return Err(format!("EEPROM byte {} doesn't fit in 8 bits", val));
+        }
+        self.image.eeprom.push(val as u8);
+        Ok(())
+    }
+
+    /// Imports `header`, included at `span`.
+    fn include(&mut self, header: &'static Header, span: Span)
+        -> Result<(), String>
+    {
+        for &(ref name, val) in &header.equs {
+            let old_val =
+                *self.constants.values.entry(&name[..]).or_insert(val);
+            if old_val != val {
+                return Err(format!("'{}' is already defined", name));
+            }
+            self.constant_defs.entry(&name[..]).or_insert(span);
+        }
+        if let Some(ref gpr) = header.gpr {
+            self.gpr = Some(gpr);
+        }
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: &'s str, val: i64, span: Span)
+        -> Result<(), String>
+    {
+        expr::check_ident(name)?;
+        if *self.constants.values.entry(name).or_insert(val) != val {
+            return Err(format!("'{}' is already defined", name));
+        }
+        self.constant_defs.entry(name).or_insert(span);
+        Ok(())
+    }
+
+    fn start_data_section(&mut self, name: &'s str, shared: bool, span: Span)
+        -> Result<(), String>
+    {
+        expr::check_ident(name)?;
+        if self.cblock.is_some() {
+            return Err("data section inside cblock".to_string());
+        }
+        if self.data_sections.iter()
+            .any(|sec| !sec.cblock && sec.name == name)
+        {
+            return Err(format!("data section '{}' already exists", name));
+        }
+        self.data_sections.push(DataSection::new(name, shared, span));
+        Ok(())
+    }
+
+    fn reserve(&mut self, name: &'s str, size: i64, span: Span)
+        -> Result<(), String>
+    {
+        expr::check_ident(name)?;
+        if self.cblock.is_some() {
+            return Err("res inside cblock".to_string());
+        }
+        let sec = self.data_sections.last_mut()
+            .filter(|sec| !sec.cblock)
+            .ok_or("res outside of a data section")?;
+        if !(0..=0x80).contains(&size) {
+            return Err(format!("can't reserve {} bytes", size));
+        }
+        sec.push(name, size as u16, span);
+        Ok(())
+    }
+
+    /// `dir` is the `cblock` itself, which stands in for a section name.
+    fn start_cblock(&mut self, dir: &'s str, addr: Option<i64>, span: Span)
+        -> Result<(), String>
+    {
+        if self.cblock.is_some() {
+            return Err("cblock inside cblock".to_string());
+        }
+        let mut sec = DataSection::new(dir, false, span);
+        sec.cblock = true;
+        if let Some(addr) = addr {
+            if !(0..0x1000).contains(&addr) {
+                return Err(format!("RAM address {} is out of range", addr));
+            }
+            sec.addr = Some(addr as u16);
+        }
+        self.data_sections.push(sec);
+        self.cblock = Some(span);
+        Ok(())
+    }
+
+    fn end_cblock(&mut self) -> Result<(), String> {
+        self.cblock.take().ok_or("endc without cblock")?;
+        Ok(())
+    }
+
+    fn cblock_var(&mut self, name: &'s str, size: i64, span: Span)
+        -> Result<(), String>
+    {
+        expr::check_ident(name)?;
+        if self.cblock.is_none() {
+            return Err(format!("'{}' isn't an instruction", name));
+        }
+        if !(0..=0x80).contains(&size) {
+            return Err(format!("can't reserve {} bytes", size));
+        }
+        self.data_sections.last_mut().unwrap().push(name, size as u16, span);
+        Ok(())
+    }
+
+    /// Places data sections, then evaluates and encodes every operand.
+    fn resolve(&mut self) -> Result<(), String> {
+        if !self.data_sections.is_empty() {
+            let mut dev = *self.device.ok_or("data sections need a device")?;
+            if let Some(gpr) = self.gpr {
+                dev.gpr = gpr;
+            }
+            ram::alloc(&dev, &mut self.data_sections)?;
+        }
+
+        let mut syms = self.constants.clone();
+        for stmt in &self.stmts {
+            let addr = i64::from(stmt.addr);
+            for &(label, span) in &stmt.labels {
+                let is_dup = if label.starts_with('.') {
+                    syms.locals.insert((stmt.scope.unwrap(), label), addr)
+                        .is_some()
+                } else if label.starts_with(|c: char| c.is_ascii_digit()) {
+                    syms.anon.entry(label).or_default().push(addr);
+                    false
+                } else {
+                    syms.values.insert(label, addr).is_some()
+                };
+                if is_dup {
+                    return Err(format!(
+                        "line {}: '{}' is already defined",
+                        span.line,
+                        stmt.symbol_name(label).unwrap(),
+                    ));
+                }
+            }
+        }
+        for (name, addr, _, span) in self.variables() {
+            if syms.values.insert(name, i64::from(addr)).is_some() {
+                return Err(format!(
+                    "line {}: '{}' is already defined", span.line, name,
+                ));
+            }
+        }
+        for sec in &self.data_sections {
+            if !sec.cblock {
+                syms.sizes.insert(sec.name, i64::from(sec.size()));
+            }
+            for var in &sec.vars {
+                syms.sizes.insert(var.name, i64::from(var.size));
+            }
+        }
+        for (&start, &words) in &self.tables {
+            // the table runs on through unlabelled tables right after it
+            let mut end = start + words;
+            while let Some(&more) = self.tables.get(&end) {
+                if more == 0 || !self.stmts[end as usize].labels.is_empty() {
+                    break;
+                }
+                end += more;
+            }
+            let labels = self.stmts.get(start as usize)
+                .map_or(&[][..], |stmt| &stmt.labels[..]);
+            for &(label, _) in labels {
+                if label.starts_with(is_ident_initial) {
+                    syms.sizes.insert(label, i64::from(end - start));
+                }
+            }
+        }
+
+        for &(addr, ref raws, span) in &self.indirect {
+            let err = |msg: String| format!("line {}: {}", span.line, msg);
+            match self.stmts.get(addr as usize) {
+                Some(stmt) if [Flow::IndirectBranch, Flow::IndirectCall]
+                    .contains(&stmt.insn.desc.flow) => (),
+                _ => return Err(err(
+                    "targets must come right before callw or brw".to_string(),
+                )),
+            }
+            let mut targets = vec![];
+            for raw in raws {
+                let target = expr::eval(raw, &syms).map_err(&err)?;
+                if !(0..0x8000).contains(&target) {
+                    return Err(err(format!(
+                        "target {} is out of program memory", target,
+                    )));
+                }
+                targets.push(target as u16);
+            }
+            self.indirect_targets.insert(addr, targets);
+        }
+
+        for stmt in &mut self.stmts {
+            syms.here = Some(i64::from(stmt.addr));
+            syms.scope = stmt.scope;
+            let lookup = &syms;
+            let line = stmt.span.line;
+            let err = |msg: String| format!("line {}: {}", line, msg);
+            let desc = stmt.insn.desc;
+            for (i, (opd, src)) in
+                desc.operands.iter().zip(&stmt.opds).enumerate()
+            {
+                let val = match *src {
+                    OpdSrc::Expr(raw) => expr::eval(raw, lookup)
+                        .map_err(&err)?,
+                    OpdSrc::BankOf(raw) => expr::eval(raw, lookup)
+                        .map_err(&err)? >> 7,
+                    OpdSrc::High(raw) => (expr::eval(raw, lookup)
+                        .map_err(&err)? >> 8) & 0xFF,
+                    OpdSrc::Low(raw) => expr::eval(raw, lookup)
+                        .map_err(&err)? & 0xFF,
+                    OpdSrc::Const(val) => val,
+                    OpdSrc::Dest(dest) => (dest != Some("W")) as i64,
+                    OpdSrc::Missing => 0,
+                };
+                stmt.insn.operands[i].raw = opd.kind.encode(val, stmt.addr)
+                    .map_err(&err)?;
+                if let OpdDescKind::F = opd.kind {
+                    self.regs.insert(stmt.addr, val as u16);
+                }
+            }
+        }
+        self.symbols = syms;
+        Ok(())
+    }
+
+    /// Evaluates each `assert`, which can use `cycles` and `min_cycles`.
+    fn check_asserts(&self) -> Result<(), String> {
+        let mut analysis = cycles::Analysis::new(self);
+        let mut syms = self.symbols.clone();
+        for assert in &self.asserts {
+            syms.here = Some(i64::from(assert.addr));
+            syms.scope = assert.scope;
+            let line = assert.span.line;
+            let err = |msg: String| format!("line {}: {}", line, msg);
+            let mut funcs = |name: &str, args: &[i64]| {
+                analysis.funcs(name, args)
+            };
+            let val = expr::eval_with(assert.cond, &syms, &mut funcs)
+                .map_err(&err)?;
+            if val == 0 {
+                return Err(err(format!(
+                    "assertion failed: {}",
+                    assert.msg.as_ref().map_or(assert.cond, |msg| &msg[..]),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn ram_used(&self) -> u16 {
+        self.data_sections.iter().map(|sec| sec.size()).sum()
+    }
+
+    /// (name, banked address, section name, definition) for every
+    /// variable, in declaration order. Addresses are zero until `resolve`.
+    pub(crate) fn variables(&self) -> Vec<(&'s str, u16, &'s str, Span)> {
+        let mut vars = vec![];
+        for sec in &self.data_sections {
+            for var in &sec.vars {
+                let addr = sec.addr.unwrap_or(0) + var.offset;
+                vars.push((var.name, addr, sec.name, var.span));
+            }
+        }
+        vars
+    }
+
+    /// Named labels, with local ones under their full names. Anonymous
+    /// labels aren't included.
+    pub(crate) fn labels(&self) -> BTreeMap<String, u16> {
+        let mut labels = BTreeMap::new();
+        for stmt in &self.stmts {
+            for &(label, _) in &stmt.labels {
+                if let Some(name) = stmt.symbol_name(label) {
+                    labels.insert(name, stmt.addr);
+                }
+            }
+        }
+        labels
+    }
+}
+
+fn build_tr_unit<'s>(input: &'s str, path: &'s str)
+    -> Result<TrUnit<'s>, String>
+{
     let nop_insn =
         INSN_DESCS.iter().find(|desc| desc.mnemonic == "nop").unwrap();
 
@@ -118,53 +805,818 @@ pub fn parse_tr_unit(input: &str) -> Result<String, String> {
     let tr_unit_st = Parser::parse(&g, "tr_unit", input)
         .map_err(|e| format!("{}", e))?;
 
-    let mut tr_unit = TrUnit(vec![]);
-
-    println!("{:?}", tr_unit_st);
+    let mut tr_unit = TrUnit { input, path, ..Default::default() };
 
+    let mut addr = 0;
     let mut line_sts = tr_unit_st.iter("line").peekable();
+    // the last global label
+    let mut scope = None;
+    let mut blocks = pseudo::Blocks::default();
     'outer: while line_sts.peek().is_some() {
         let mut labels = vec![];
-        let mut insn = None;
-        while insn.is_none() {
+        let mut refs = vec![];
+        let mut targets = vec![];
+        let mut targets_span = None;
+        let mut repeats = None;
+        let mut insns = vec![];
+        let mut expanded = false;
+        let mut span = None;
+        while insns.is_empty() {
             if let Some(line_st) = line_sts.next() {
+                span = Some(Span::of(input, line_st.raw(input)));
                 let label = line_st.get_or_empty("label");
                 assert!(label.len() <= 1);
                 if let Some(label) = label.first() {
-                    labels.push(label.raw(input));
+                    let label = label.raw(input);
+                    let label_span = Span::of(input, label);
+                    if !label.starts_with(|c: char| c.is_ascii_digit()) {
+                        expr::check_ident(label.trim_start_matches('.'))
+                            .map_err(|msg| {
+                                format!("line {}: {}", label_span.line, msg)
+                            })?;
+                    }
+                    if label.starts_with('.') && scope.is_none() {
+                        return Err(format!(
+                            "line {}: local label '{}' needs a global label \
+                                before it",
+                            label_span.line, label,
+                        ));
+                    }
+                    if label.starts_with(is_ident_initial) {
+                        scope = Some(label);
+                    }
+                    labels.push((label, label_span));
+                }
+                if let Some(comment) = line_st.get_or_empty("comment").first()
+                {
+                    let line = span.unwrap().line;
+                    let names = lint::nolint(comment.raw(input))
+                        .map_err(|msg| format!("line {}: {}", line, msg))?;
+                    if let Some(names) = names {
+                        tr_unit.nolint.insert(line, names);
+                    }
+                }
+                for var_st in line_st.get_or_empty("cblock_var") {
+                    let name = var_st.get_or_empty("name")[0].raw(input);
+                    let var_span = Span::of(input, name);
+                    let err = |msg: String| {
+                        format!("line {}: {}", var_span.line, msg)
+                    };
+                    let size = match var_st.get_or_empty("size").first() {
+                        Some(size_st) => {
+                            let raw = size_st.raw(input);
+                            tr_unit.dir_refs.extend(expr::idents(raw)
+                                .into_iter()
+                                .map(|name| (name, scope, var_span)));
+                            expr::eval(raw, &tr_unit.constants)
+                                .map_err(&err)?
+                        },
+                        None => 1,
+                    };
+                    tr_unit.cblock_var(name, size, var_span).map_err(&err)?;
+                }
+                let dir = line_st.get_or_empty("dir");
+                assert!(dir.len() <= 1);
+                if let Some(dir) = dir.first() {
+                    let dir_span = Span::of(input, dir.raw(input));
+                    let err = |msg: String| {
+                        format!("line {}: {}", dir_span.line, msg)
+                    };
+                    for opd in &["addr", "val"] {
+                        for opd_st in line_st.get_or_empty(opd).iter() {
+                            tr_unit.dir_refs.extend(
+                                expr::idents(opd_st.raw(input)).into_iter()
+                                    .map(|name| (name, scope, dir_span)),
+                            );
+                        }
+                    }
+                    match dir.raw(input) {
+                        "device" => {
+                            let name = line_st.get_or_empty("device")[0]
+                                .raw(input);
+                            tr_unit.select_device(name).map_err(&err)?;
+                        },
+                        "config" if !line_st.get_or_empty("addr")
+                            .is_empty() =>
+                        {
+                            let addr = expr::eval(
+                                line_st.get_or_empty("addr")[0].raw(input),
+                                &tr_unit.constants,
+                            ).map_err(&err)?;
+                            let val = expr::eval(
+                                line_st.get_or_empty("val")[0].raw(input),
+                                &tr_unit.constants,
+                            ).map_err(&err)?;
+                            tr_unit.set_config_word(addr, val).map_err(&err)?;
+                        },
+                        "config" => {
+                            for setting_st in
+                                line_st.get_or_empty("setting").iter()
+                            {
+                                let name = setting_st.get_or_empty("name")[0]
+                                    .raw(input);
+                                let value = setting_st.get_or_empty("value")
+                                    [0].raw(input);
+                                tr_unit.set_config(name, value)
+                                    .map_err(&err)?;
+                            }
+                        },
+                        "lint" => {
+                            for setting_st in
+                                line_st.get_or_empty("setting").iter()
+                            {
+                                let name = setting_st.get_or_empty("name")[0]
+                                    .raw(input);
+                                let value = setting_st.get_or_empty("value")
+                                    [0].raw(input);
+                                let level = lint::setting(name, value)
+                                    .map_err(&err)?;
+                                tr_unit.lint_levels
+                                    .push((dir_span.line, name, level));
+                            }
+                        },
+                        "include" => {
+                            let path = expr::unescape(
+                                line_st.get_or_empty("path")[0].raw(input),
+                            );
+                            let header = inc::load(&path, tr_unit.path)
+                                .map_err(&err)?;
+                            tr_unit.include(header, dir_span)
+                                .map_err(&err)?;
+                        },
+                        "targets" => {
+                            targets.extend(
+                                line_st.get_or_empty("val").iter()
+                                    .map(|st| st.raw(input)),
+                            );
+                            targets_span = Some(dir_span);
+                        },
+                        "repeats" => {
+                            let mut counts = vec![];
+                            for val_st in line_st.get_or_empty("val").iter() {
+                                let count = expr::eval(
+                                    val_st.raw(input), &tr_unit.constants,
+                                ).map_err(&err)?;
+                                counts.push(count);
+                            }
+                            let (lo, hi) =
+                                (counts[0], *counts.last().unwrap());
+                            if lo < 0 || hi < lo || hi > i64::from(u32::MAX) {
+                                return Err(err(format!(
+                                    "bad repeat count {}..{}", lo, hi,
+                                )));
+                            }
+                            repeats = Some(((lo as u64, hi as u64), dir_span));
+                        },
+                        "assert" => {
+                            let cond = line_st.get_or_empty("val")[0]
+                                .raw(input);
+                            let msg = line_st.get_or_empty("msg").first()
+                                .map(|st| expr::unescape(st.raw(input)));
+                            tr_unit.asserts.push(Assert {
+                                addr, scope, cond, msg, span: dir_span,
+                            });
+                        },
+                        "clock" => {
+                            if tr_unit.clock.is_some() {
+                                return Err(err(
+                                    "clock already set".to_string(),
+                                ));
+                            }
+                            let val = expr::eval(
+                                line_st.get_or_empty("val")[0].raw(input),
+                                &tr_unit.constants,
+                            ).map_err(&err)?;
+                            let hz = match line_st.get_or_empty("unit")[0]
+                                .raw(input)
+                            {
+                                "MHz" => val.checked_mul(1_000_000),
+                                "kHz" => val.checked_mul(1_000),
+                                _ => Some(val),
+                            };
+                            match hz {
+                                Some(hz) if hz > 0 =>
+                                    tr_unit.clock = Some(hz as u64),
+                                _ => return Err(err(format!(
+                                    "bad clock frequency {}", val,
+                                ))),
+                            }
+                        },
+                        "delay_scratch" => {
+                            tr_unit.delay_scratch = line_st
+                                .get_or_empty("val")
+                                .iter()
+                                .map(|st| st.raw(input))
+                                .collect();
+                        },
+                        "idlocs" => {
+                            for val_st in line_st.get_or_empty("val").iter() {
+                                let val = expr::eval(
+                                    val_st.raw(input), &tr_unit.constants,
+                                ).map_err(&err)?;
+                                tr_unit.push_user_id(val).map_err(&err)?;
+                            }
+                        },
+                        "udata" | "udata_shr" => {
+                            let name = line_st.get_or_empty("section")[0]
+                                .raw(input);
+                            tr_unit.start_data_section(
+                                name, dir.raw(input) == "udata_shr", dir_span,
+                            ).map_err(&err)?;
+                        },
+                        "cblock" => {
+                            let addr = match line_st.get_or_empty("addr")
+                                .first()
+                            {
+                                Some(addr_st) => Some(expr::eval(
+                                    addr_st.raw(input), &tr_unit.constants,
+                                ).map_err(&err)?),
+                                None => None,
+                            };
+                            tr_unit.start_cblock(
+                                dir.raw(input), addr, dir_span,
+                            ).map_err(&err)?;
+                        },
+                        "endc" => tr_unit.end_cblock().map_err(&err)?,
+                        "equ" => {
+                            let name = line_st.get_or_empty("const")[0]
+                                .raw(input);
+                            let val = expr::eval(
+                                line_st.get_or_empty("val")[0].raw(input),
+                                &tr_unit.constants,
+                            ).map_err(&err)?;
+                            tr_unit.define_constant(
+                                name, val, Span::of(input, name),
+                            ).map_err(&err)?;
+                        },
+                        "org" => {
+                            let to = expr::eval(
+                                line_st.get_or_empty("addr")[0].raw(input),
+                                &tr_unit.constants,
+                            ).map_err(&err)?;
+                            if to < i64::from(addr) || to > 0x8000 {
+                                return Err(err(format!(
+                                    "can't org to 0x{:X} from 0x{:X}",
+                                    to, addr,
+                                )));
+                            }
+                            // the gap reads as erased program memory
+                            if to > i64::from(addr) {
+                                let words = to as u16 - addr;
+                                tr_unit.gaps.insert(addr, words);
+                            }
+                            while i64::from(addr) < to {
+                                tr_unit.stmts.push(Stmt {
+                                    labels: vec![],
+                                    insn: Insn {
+                                        desc: &DATA_WORD_DESC,
+                                        operands: [
+                                            Opd { raw: 0 },
+                                            Opd { raw: 0 },
+                                        ],
+                                    },
+                                    opds: vec![OpdSrc::Const(0x3FFF)],
+                                    refs: vec![],
+                                    addr,
+                                    scope,
+                                    span: dir_span,
+                                    expanded: true,
+                                });
+                                addr += 1;
+                            }
+                        },
+                        "res" => {
+                            let name = line_st.get_or_empty("var")[0]
+                                .raw(input);
+                            let size = expr::eval(
+                                line_st.get_or_empty("val")[0].raw(input),
+                                &tr_unit.constants,
+                            ).map_err(&err)?;
+                            tr_unit.reserve(name, size, Span::of(input, name))
+                                .map_err(&err)?;
+                        },
+                        "de" => {
+                            for val_st in line_st.get_or_empty("val").iter() {
+                                let raw = val_st.raw(input);
+                                if raw.starts_with('"') {
+                                    for c in expr::unescape(raw).chars() {
+                                        tr_unit.push_eeprom(c as i64)
+                                            .map_err(&err)?;
+                                    }
+                                } else {
+                                    let val = expr::eval(
+                                        raw, &tr_unit.constants,
+                                    ).map_err(&err)?;
+                                    tr_unit.push_eeprom(val).map_err(&err)?;
+                                }
+                            }
+                        },
+                        _ => unreachable!(),
+                    }
+                }
+                for opd in &["a", "f", "b", "k"] {
+                    for opd_st in line_st.get_or_empty(opd).iter() {
+                        refs.extend(expr::idents(opd_st.raw(input)));
+                    }
                 }
                 // build insn from 'm', if present
                 let m = line_st.get_or_empty("m");
                 assert!(m.len() <= 1);
                 if let Some(m) = m.first() {
-                    insn = Some(Insn {
-                        desc: nop_insn,
-                        operands: [
-                            Opd { raw: 0 },
-                            Opd { raw: 0 },
-                        ],
-                    });  // FIXME
+                    let m = m.raw(input);
+                    let cap = |name: &str| {
+                        line_st.get_or_empty(name).first()
+                            .map(|st| st.raw(input))
+                    };
+                    expanded = true;
+                    insns = match m {
+                        _ if pseudo::is_block_op(m) => {
+                            let m_span = Span::of(input, m);
+                            let stmts = &mut tr_unit.stmts;
+                            let refs = &mut refs;
+                            blocks.lower(m, cap, m_span, addr, stmts, refs)
+                                .map_err(|msg| {
+                                    format!("line {}: {}", m_span.line, msg)
+                                })?
+                        },
+                        "moviw" | "movwi" => vec![pseudo::moviwwi_mm(m, cap)],
+                        "delay" | "delay_cycles" => {
+                            let line = span.unwrap().line;
+                            let err = |msg: String| {
+                                format!("line {}: {}", line, msg)
+                            };
+                            let val = expr::eval(
+                                cap("k").unwrap(), &tr_unit.constants,
+                            ).map_err(&err)?;
+                            let (cycles, error) = match cap("unit") {
+                                Some(unit) => {
+                                    let (cycles, error) = delay::cycles(
+                                        val, unit, tr_unit.clock,
+                                    ).map_err(&err)?;
+                                    (cycles, Some(error))
+                                },
+                                None if val >= 0 => (val as u64, None),
+                                None => return Err(err(format!(
+                                    "can't delay for {} cycles", val,
+                                ))),
+                            };
+                            let (insns, repeats) = delay::lower(
+                                cycles, &tr_unit.delay_scratch, addr,
+                            ).map_err(&err)?;
+                            for (at, taken) in repeats {
+                                tr_unit.repeats.insert(at, (taken, taken));
+                            }
+                            tr_unit.delays.push((line, cycles, error));
+                            insns
+                        },
+                        "dt" | "da" => {
+                            let vals: Vec<_> = line_st.get_or_empty("k")
+                                .iter()
+                                .map(|st| st.raw(input))
+                                .collect();
+                            let words = pseudo::table(m, &vals)
+                                .map_err(|msg| format!(
+                                    "line {}: {}", span.unwrap().line, msg,
+                                ))?;
+                            if !words.is_empty() {
+                                tr_unit.tables
+                                    .insert(addr, words.len() as u16);
+                            }
+                            words
+                        },
+                        "tris" => {
+                            // TRISA is tris_a
+                            let port = cap("t").unwrap()[4..].to_lowercase();
+                            let desc = INSN_DESCS.iter()
+                                .find(|desc| {
+                                    desc.mnemonic == format!("tris_{}", port)
+                                })
+                                .unwrap();
+                            expanded = false;
+                            vec![(desc, vec![])]
+                        },
+                        _ => match pseudo::expand(m, cap) {
+                            Some(insns) => insns,
+                            None => {
+                                let desc = INSN_DESCS.iter()
+                                    .find(|desc| desc.mnemonic == m)
+                                    .ok_or_else(|| format!(
+                                        "line {}: '{}' isn't supported",
+                                        span.unwrap().line, m,
+                                    ))?;
+                                expanded = false;
+                                vec![(desc, OpdSrc::for_insn(desc, cap))]
+                            },
+                        },
+                    };
                 }
             } else if !labels.is_empty() {
-                insn = Some(Insn {
-                    desc: nop_insn,
+                insns = vec![(nop_insn, vec![])];
+            } else {
+                break 'outer; // sorry
+            }
+        }
+        if let Some(span) = targets_span {
+            tr_unit.indirect.push((addr, targets, span));
+        }
+        if let Some((counts, span)) = repeats {
+            let jump = insns.iter()
+                .position(|&(desc, _)| {
+                    [Flow::Branch, Flow::IndirectBranch].contains(&desc.flow)
+                })
+                .ok_or_else(|| format!(
+                    "line {}: repeats must come right before a goto, bra or \
+                        brw",
+                    span.line,
+                ))?;
+            tr_unit.repeats.insert(addr + jump as u16, counts);
+        }
+        for (desc, opds) in insns {
+            tr_unit.stmts.push(Stmt {
+                // only the first word gets the labels and refs
+                labels: mem::take(&mut labels),
+                insn: Insn {
+                    desc,
                     operands: [
                         Opd { raw: 0 },
                         Opd { raw: 0 },
                     ],
-                });
-            } else {
-                break 'outer; // sorry
-            }
+                },
+                opds,
+                refs: mem::take(&mut refs),
+                addr,
+                scope,
+                span: span.unwrap(),
+                expanded,
+            });
+            addr += 1;
         }
-        tr_unit.0.push((labels, insn.unwrap()));
+    }
+    for (label, span, at) in blocks.finish()? {
+        match tr_unit.stmts.get_mut(at as usize) {
+            Some(stmt) => stmt.labels.push((label, span)),
+            // a block that ends the program goes just past it
+            None => tr_unit.define_constant(label, i64::from(at), span)
+                .map_err(|msg| format!("line {}: {}", span.line, msg))?,
+        }
+    }
+    if let Some(span) = tr_unit.cblock {
+        return Err(format!("line {}: cblock is never closed", span.line));
     }
 
+    tr_unit.resolve()?;
+    stack::analyze(&tr_unit)?.check(&tr_unit)?;
+    tr_unit.check_asserts()?;
+    tr_unit.warnings = lint::check(&tr_unit)?;
+    Ok(tr_unit)
+}
+
+/// `input` with its identifiers normalized to NFC, ready for `assemble`.
+pub fn normalize(input: &str) -> String {
+    expr::nfc(input).into_owned()
+}
+
+/// Assembles `input`, read from `path` (see `normalize`). The outputs
+/// below all work from the result.
+pub fn assemble<'s>(input: &'s str, path: &'s str)
+    -> Result<TrUnit<'s>, String>
+{
+    build_tr_unit(input, path)
+}
+
+/// Assembles `input` and renders the result for debugging.
+pub fn parse_tr_unit(input: &str) -> Result<String, String> {
+    let input = normalize(input);
+    let tr_unit = assemble(&input, "")?;
     Ok(format!("{:?}", tr_unit))
 }
 
+/// Rewrites MPASM source into this assembler's syntax, keeping every line
+/// where it was (see `mpasm`).
+pub fn mpasm(input: &str) -> Result<String, String> {
+    mpasm::translate(input)
+}
+
+/// Whether `input` is MPASM source that says so with a `syntax mpasm` line
+/// before any code, and should go through `mpasm`.
+pub fn selects_mpasm(input: &str) -> bool {
+    mpasm::selected(input)
+}
+
+/// The warnings for `tr_unit`, one per line (see `lint`).
+pub fn lint(tr_unit: &TrUnit) -> Vec<String> {
+    tr_unit.warnings.iter()
+        .map(|&(span, ref msg)| {
+            format!("line {}: warning: {}", span.line, msg)
+        })
+        .collect()
+}
+
+/// A listing of `tr_unit` (see `listing::listing`).
+pub fn listing(tr_unit: &TrUnit) -> String {
+    listing::listing(tr_unit, tr_unit.input)
+}
+
+/// A symbol map with cross-references (see `map::map`).
+pub fn map(tr_unit: &TrUnit) -> String {
+    map::map(tr_unit, tr_unit.path)
+}
+
+/// Source-line debug information (see `debug_info`).
+pub fn debug_info(tr_unit: &TrUnit) -> String {
+    debug_info::debug_info(tr_unit, tr_unit.path)
+}
+
+/// Intel HEX (see `hex`).
+pub fn hex(tr_unit: &TrUnit) -> String {
+    hex::hex(tr_unit)
+}
+
+/// An ELF executable (see `elf`).
+pub fn elf(tr_unit: &TrUnit) -> Vec<u8> {
+    elf::elf(tr_unit, tr_unit.path)
+}
+
+/// A Microchip COFF file (see `coff`).
+pub fn coff(tr_unit: &TrUnit) -> Vec<u8> {
+    coff::coff(tr_unit, tr_unit.path)
+}
+
 #[cfg(test)]
 #[test]
 fn parse_empty_string() {
     parse_tr_unit("").unwrap();
 }
+
+#[cfg(test)]
+#[test]
+fn test_set_config() {
+    let mut tr_unit = TrUnit::default();
+    assert!(tr_unit.set_config("FOSC", "INTOSC").is_err());
+    assert!(tr_unit.select_device("nope").is_err());
+    tr_unit.select_device("PIC16F1829").unwrap();
+    tr_unit.set_config("FOSC", "INTOSC").unwrap();
+    tr_unit.set_config("WDTE", "OFF").unwrap();
+    tr_unit.set_config("FOSC", "INTOSC").unwrap();
+    assert!(tr_unit.set_config("FOSC", "HS").is_err());
+    assert!(tr_unit.set_config("MCLRE", "MAYBE").is_err());
+    assert!(tr_unit.set_config("BOGUS", "ON").is_err());
+    assert_eq!(tr_unit.image().config[&0x8007], 0x3FE4);
+    assert_eq!(tr_unit.image().config[&0x8008], 0x3FFF);
+
+    assert!(tr_unit.set_config_word(0x8007, 0).is_err());
+    assert!(tr_unit.set_config_word(0x8009, 0).is_err());
+    assert!(tr_unit.set_config_word(0x8008, 0x4000).is_err());
+    tr_unit.set_config_word(0x8008, 0x1FFF).unwrap();
+    assert!(tr_unit.set_config_word(0x8008, 0x1FFF).is_err());
+    assert!(tr_unit.set_config("LVP", "OFF").is_err());
+    assert_eq!(tr_unit.image().config[&0x8008], 0x1FFF);
+
+    let tr_unit = build_tr_unit("\
+device PIC16F1829
+config FOSC = INTOSC, WDTE = OFF
+config 0x8008, 0x1FFF
+", "test.asm").unwrap();
+    assert_eq!(tr_unit.image().config[&0x8007], 0x3FE4);
+    assert_eq!(tr_unit.image().config[&0x8008], 0x1FFF);
+    assert_eq!(
+        build_tr_unit("config FOSC = INTOSC\n", "test.asm").unwrap_err(),
+        "line 1: config before device",
+    );
+    assert!(build_tr_unit(
+        "device PIC16F1829\nconfig FOSC = INTOSC\nconfig FOSC = HS\n",
+        "test.asm",
+    ).unwrap_err().starts_with("line 3: "));
+}
+
+#[cfg(test)]
+#[test]
+fn test_user_id_and_eeprom() {
+    let mut tr_unit = TrUnit::default();
+    assert!(tr_unit.push_eeprom(1).is_err());
+    tr_unit.select_device("PIC16F1829").unwrap();
+    for i in 0..4 {
+        tr_unit.push_user_id(i).unwrap();
+    }
+    assert!(tr_unit.push_user_id(4).is_err());
+    assert!(tr_unit.push_eeprom(0x100).is_err());
+    tr_unit.push_eeprom(-1).unwrap();
+    for _ in 1..256 {
+        tr_unit.push_eeprom(0).unwrap();
+    }
+    assert!(tr_unit.push_eeprom(0).is_err());
+
+    let words = tr_unit.image().words();
+    assert_eq!(&words[..2], &[(0x8000, 0), (0x8001, 1)]);
+    assert_eq!(words[4], (0xF000, 0xFF));
+    assert_eq!(words.last(), Some(&(0xF0FF, 0)));
+}
+
+#[cfg(test)]
+#[test]
+fn test_literals() {
+    let tr_unit = build_tr_unit(r#"device PIC16F1829
+    movlw 'A'
+    movlw '\x41' + 1
+    retlw '\''
+    dt 'A', "B\n"
+    de "hi", '!'
+"#, "test.asm").unwrap();
+    let words: Vec<_> =
+        tr_unit.stmts().iter().map(|stmt| stmt.insn.encode()).collect();
+    assert_eq!(words, vec![0x3041, 0x3042, 0x3427, 0x3441, 0x3442, 0x340A]);
+    assert_eq!(&tr_unit.image().words()[..], &[
+        (0xF000, 0x68), (0xF001, 0x69), (0xF002, 0x21),
+    ]);
+    assert!(build_tr_unit("    movlw 'AB'\n", "test.asm").is_err());
+    assert_eq!(
+        build_tr_unit("    dt \"é\"\n    da \"Aé\"\n", "test.asm")
+            .unwrap_err(),
+        "line 2: da can't hold 'é' (over 0x7F)",
+    );
+    assert_eq!(
+        build_tr_unit("    dt \"ā\"\n", "test.asm").unwrap_err(),
+        "line 1: dt can't hold 'ā' (over 0xFF)",
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_resolve() {
+    let tr_unit = build_tr_unit("\
+device PIC16F1829
+udata vars
+filler res 80
+udata more
+count res 1
+    banksel count
+    decf count, F
+    movf count, W
+    bra 0
+    tris TRISB
+", "test.asm").unwrap();
+
+    assert_eq!(tr_unit.variables()[1].1, 0x0A0);
+    let words: Vec<_> =
+        tr_unit.stmts().iter().map(|stmt| stmt.insn.encode()).collect();
+    assert_eq!(words, vec![0x0021, 0x03A0, 0x0820, 0x33FC, 0x0066]);
+    assert_eq!(tr_unit.ram_used(), 81);
+
+    for (src, msg) in &[
+        ("udata vars\n    nop\n", "data sections need a device"),
+        ("x res 1\n", "line 1: res outside of a data section"),
+        (
+            "device PIC16F1829\nudata a\nudata a\n",
+            "line 3: data section 'a' already exists",
+        ),
+        (
+            "device PIC16F1829\nudata a\nx res 1\nx:\n",
+            "line 3: 'x' is already defined",
+        ),
+        ("    nop\n    ifc 0x70, 0\n", "line 2: 'ifc' isn't supported"),
+    ] {
+        assert_eq!(build_tr_unit(src, "test.asm").unwrap_err(), *msg);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_cblock() {
+    let tr_unit = build_tr_unit("\
+device PIC16F1829
+cblock 0x20
+    flags, buf[4]
+endc
+udata vars
+count res 1
+cblock
+    tmp
+endc
+    movf tmp, W
+    movlw sizeof(buf)
+", "test.asm").unwrap();
+    let vars: Vec<_> = tr_unit.variables().iter()
+        .map(|&(name, addr, section, _)| (name, addr, section))
+        .collect();
+    assert_eq!(vars, vec![
+        ("flags", 0x020, "cblock"),
+        ("buf", 0x021, "cblock"),
+        ("count", 0x025, "vars"),
+        ("tmp", 0x026, "cblock"),
+    ]);
+    let words: Vec<_> =
+        tr_unit.stmts().iter().map(|stmt| stmt.insn.encode()).collect();
+    assert_eq!(words, vec![0x0826, 0x3004]);
+
+    for (src, msg) in &[
+        ("cblock\n    a\n", "line 1: cblock is never closed"),
+        ("endc\n", "line 1: endc without cblock"),
+        ("    a, b\n", "line 1: 'a' isn't an instruction"),
+        ("cblock\n    a\n    b res 1\nendc\n", "line 3: res inside cblock"),
+        ("cblock\ncblock\n", "line 2: cblock inside cblock"),
+    ] {
+        assert_eq!(build_tr_unit(src, "test.asm").unwrap_err(), *msg);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_equ_and_org() {
+    let tr_unit = build_tr_unit("\
+LIMIT equ 3 * 4
+    movlw LIMIT
+    org 0x4
+here:
+    goto here
+", "test.asm").unwrap();
+    let words: Vec<_> =
+        tr_unit.stmts().iter().map(|stmt| stmt.insn.encode()).collect();
+    assert_eq!(words, vec![0x300C, 0x3FFF, 0x3FFF, 0x3FFF, 0x2804]);
+    assert_eq!(tr_unit.labels()["here"], 4);
+    assert_eq!(tr_unit.words_used(), 2);
+    assert_eq!(
+        hex(&tr_unit),
+        ":020000000C30C2\n:020008000428CA\n:00000001FF\n",
+    );
+
+    for (src, msg) in &[
+        ("    nop\n    nop\n    org 1\n", "line 3: can't org to 0x1 from 0x2"),
+        ("X equ 1\nX equ 2\n", "line 2: 'X' is already defined"),
+        ("X equ 1\nX:\n", "line 2: 'X' is already defined"),
+    ] {
+        assert_eq!(build_tr_unit(src, "test.asm").unwrap_err(), *msg);
+    }
+
+    let src = mpasm("\
+\tlist p=16f1829
+N\tEQU 2
+#define\tTWICE N * 2
+\tcblock 0x70
+\t\tw_save, buf:TWICE
+\tendc
+\torg 1
+\tmovwf buf + N
+").unwrap();
+    let tr_unit = build_tr_unit(&src, "test.asm").unwrap();
+    let words: Vec<_> =
+        tr_unit.stmts().iter().map(|stmt| stmt.insn.encode()).collect();
+    assert_eq!(words, vec![0x3FFF, 0x00F3]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_table_sizes() {
+    let tr_unit = build_tr_unit("\
+    movlw sizeof(digits)
+    movlw sizeof(msg)
+    movlw sizeof(packed)
+digits:
+    dt 1, 2, 3
+    dt \"45\"
+msg:
+    dt \"hi\", 0
+packed:
+    da \"abc\"
+", "test.asm").unwrap();
+    let sizes: Vec<_> = tr_unit.stmts()[..3].iter()
+        .map(|stmt| stmt.insn.encode() & 0xFF)
+        .collect();
+    assert_eq!(sizes, vec![5, 3, 2]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_local_labels() {
+    let src = "\
+main:
+.loop:
+    goto 1f
+1:  goto .loop
+other:
+.loop:
+1:  goto 1b
+    goto .loop
+";
+    let tr_unit = build_tr_unit(src, "test.asm").unwrap();
+    let targets: Vec<_> = tr_unit.stmts().iter()
+        .map(|stmt| stmt.insn.encode() & 0x7FF)
+        .collect();
+    assert_eq!(targets, vec![1, 0, 2, 2]);
+    let labels: Vec<_> = tr_unit.labels().into_iter().collect();
+    assert_eq!(labels, vec![
+        ("main".to_string(), 0),
+        ("main.loop".to_string(), 0),
+        ("other".to_string(), 2),
+        ("other.loop".to_string(), 2),
+    ]);
+
+    let dup = format!("{}.loop:\n    nop\n", src);
+    assert_eq!(
+        build_tr_unit(&dup, "test.asm").unwrap_err(),
+        "line 9: 'other.loop' is already defined",
+    );
+    assert!(build_tr_unit(".loop:\n    nop\n", "test.asm").unwrap_err()
+        .contains("needs a global label"));
+    assert!(build_tr_unit("    goto 1f\n1:  goto 1f\n", "test.asm")
+        .unwrap_err().contains("no anonymous label '1' after here"));
+}
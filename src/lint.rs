@@ -0,0 +1,748 @@
+//! Warnings: things that assemble fine but are probably mistakes.
+//!
+//! Each lint has a name. `lint name = deny` (or `allow`, or `warn`) sets
+//! how seriously it's taken from there on, and a `# nolint` comment, or
+//! `# nolint(name, ...)`, turns lints off for its line. The pedantic ones
+//! are off by default; `pedantic` stands for all of them.
+
+use data::{Flow, Regs};
+use device::{self, Sfr};
+use flow::Cfg;
+use std::collections::BTreeSet;
+use unicode_script::{Script, UnicodeScript};
+use {OpdSrc, Span, Stmt, TrUnit};
+
+/// How seriously to take a lint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Level {
+    Allow,
+    Warn,
+    /// Fail the assembly
+    Deny,
+}
+
+type Check = fn(&TrUnit, &Cfg, &mut Vec<(Span, String)>);
+
+/// Every lint: its name, how seriously it's taken unless a `lint` setting
+/// says otherwise, and what it looks for.
+static LINTS: &[(&str, Level, Check)] = &[
+    ("mixed_script", Level::Warn, |tr_unit, _, warnings| {
+        mixed_script(tr_unit, warnings)
+    }),
+    ("skip_hazard", Level::Warn, |tr_unit, _, warnings| {
+        skip_hazards(tr_unit, warnings)
+    }),
+    ("unannotated_indirect", Level::Warn, |tr_unit, _, warnings| {
+        unannotated_indirect(tr_unit, warnings)
+    }),
+    ("unreachable", Level::Warn, unreachable),
+    ("unused_label", Level::Warn, unused_labels),
+    ("falls_into_table", Level::Warn, falls_into_tables),
+    ("runs_past_end", Level::Warn, runs_past_end),
+    ("rmw_port", Level::Warn, |tr_unit, _, warnings| {
+        rmw_ports(tr_unit, warnings)
+    }),
+    ("read_only_write", Level::Warn, |tr_unit, _, warnings| {
+        read_only_writes(tr_unit, warnings)
+    }),
+    ("pcl_write", Level::Warn, pcl_writes),
+    ("status_write", Level::Warn, |tr_unit, _, warnings| {
+        status_writes(tr_unit, warnings)
+    }),
+    ("default_dest", Level::Warn, |tr_unit, _, warnings| {
+        default_dests(tr_unit, warnings)
+    }),
+    ("overwritten_w", Level::Allow, |tr_unit, _, warnings| {
+        overwritten_w(tr_unit, warnings)
+    }),
+];
+
+/// Whether `setting`, the name in a `lint` setting or `nolint` comment,
+/// is about lint `name`.
+fn covers(setting: &str, name: &str) -> bool {
+    setting == name || setting == "pedantic" && LINTS.iter()
+        .any(|&(lint, level, _)| lint == name && level == Level::Allow)
+}
+
+/// `value` for lint `name` in a `lint` setting.
+pub(crate) fn setting(name: &str, value: &str) -> Result<Level, String> {
+    if name != "pedantic" && !LINTS.iter().any(|&(lint, _, _)| lint == name)
+    {
+        return Err(format!("unknown lint '{}'", name));
+    }
+    match value {
+        "allow" => Ok(Level::Allow),
+        "warn" => Ok(Level::Warn),
+        "deny" => Ok(Level::Deny),
+        _ => Err(format!(
+            "lint '{}' can be allow, warn or deny, not '{}'", name, value,
+        )),
+    }
+}
+
+/// The lints a comment turns off for its line: `None` if it isn't a
+/// `nolint` comment, and an empty list for all of them.
+pub(crate) fn nolint(comment: &str) -> Result<Option<Vec<&str>>, String> {
+    let rest = comment.trim_start_matches('#').trim_start();
+    if !rest.starts_with("nolint") {
+        return Ok(None);
+    }
+    let rest = rest["nolint".len()..].trim_start();
+    if !rest.starts_with('(') {
+        return Ok(Some(vec![]));
+    }
+    let end = rest.find(')').ok_or("nolint is missing a ')'")?;
+    let names: Vec<_> = rest[1..end].split(',').map(str::trim).collect();
+    for name in &names {
+        setting(name, "allow")?;
+    }
+    Ok(Some(names))
+}
+
+/// Every warning for `tr_unit` and the lint it's from, in no particular
+/// order, whatever the settings.
+pub(crate) fn lint(tr_unit: &TrUnit) -> Vec<(Span, &'static str, String)> {
+    let cfg = Cfg::new(tr_unit);
+    let mut warnings = vec![];
+    for &(name, _, check) in LINTS {
+        let mut found = vec![];
+        check(tr_unit, &cfg, &mut found);
+        warnings.extend(
+            found.into_iter().map(|(span, msg)| (span, name, msg)),
+        );
+    }
+    warnings
+}
+
+/// The warnings left once `lint` settings and `nolint` comments have had
+/// their say, in order, or the first one that's denied as an error.
+pub(crate) fn check(tr_unit: &TrUnit) -> Result<Vec<(Span, String)>, String> {
+    let mut warnings = lint(tr_unit);
+    warnings.sort_by_key(|&(span, _, _)| (span.line, span.col));
+    let mut kept = vec![];
+    for (span, name, msg) in warnings {
+        let off = tr_unit.nolint.get(&span.line).is_some_and(|names| {
+            names.is_empty() || names.iter().any(|off| covers(off, name))
+        });
+        // The last setting before the line it's about wins.
+        let level = tr_unit.lint_levels.iter()
+            .rev()
+            .find(|&&(line, lint, _)| line < span.line && covers(lint, name))
+            .map(|&(_, _, level)| level)
+            .or_else(|| LINTS.iter()
+                .find(|&&(lint, _, _)| lint == name)
+                .map(|&(_, level, _)| level))
+            .unwrap_or(Level::Warn);
+        match level {
+            _ if off => (),
+            Level::Allow => (),
+            Level::Warn => kept.push((span, format!("{} [{}]", msg, name))),
+            Level::Deny => return Err(format!(
+                "line {}: {} [{}]", span.line, msg, name,
+            )),
+        }
+    }
+    Ok(kept)
+}
+
+/// Scripts that are written together, as in UTS #39's augmented script
+/// sets: each script is also part of the writing systems listed with it.
+static WRITING_SYSTEMS: &[(&str, &[&str])] = &[
+    ("Han", &["Japanese", "Korean", "Han with Bopomofo"]),
+    ("Hiragana", &["Japanese"]),
+    ("Katakana", &["Japanese"]),
+    ("Hangul", &["Korean"]),
+    ("Bopomofo", &["Han with Bopomofo"]),
+];
+
+/// The scripts `c` can be written in, or `None` for any (like digits and
+/// `_`).
+fn scripts(c: char) -> Option<Vec<&'static str>> {
+    let ext = c.script_extension();
+    if ext.is_common() || ext.is_inherited() {
+        return None;
+    }
+    let mut scripts: Vec<_> = ext.iter().map(Script::full_name).collect();
+    for &(script, systems) in WRITING_SYSTEMS {
+        if scripts.contains(&script) {
+            scripts.extend(systems);
+        }
+    }
+    Some(scripts)
+}
+
+/// Identifiers that mix scripts, like a Latin name with a Cyrillic 'а' in
+/// it, are usually typos or confusables. Digits and `_` don't count, and
+/// scripts that are written together, like kana and kanji, don't mix.
+fn mixed_script(tr_unit: &TrUnit, warnings: &mut Vec<(Span, String)>) {
+    let mut names = vec![];
+    for stmt in tr_unit.stmts() {
+        names.extend(stmt.labels.iter().cloned());
+        names.extend(stmt.refs.iter().map(|&name| (name, stmt.span)));
+    }
+    names.extend(
+        tr_unit.variables().into_iter().map(|(name, _, _, span)| (name, span)),
+    );
+
+    let mut seen = BTreeSet::new();
+    for (name, span) in names {
+        if !seen.insert(name) {
+            continue;
+        }
+        let mut common: Option<Vec<_>> = None;
+        for scripts in name.chars().filter_map(scripts) {
+            common = Some(match common {
+                None => scripts,
+                Some(common) => common.into_iter()
+                    .filter(|s| scripts.contains(s))
+                    .collect(),
+            });
+        }
+        if common.is_some_and(|common| common.is_empty()) {
+            let mut scripts: Vec<_> = name.chars()
+                .map(|c| c.script())
+                .filter(|&s| s != Script::Common && s != Script::Inherited)
+                .collect();
+            scripts.sort_by_key(|s| s.full_name());
+            scripts.dedup();
+            let scripts: Vec<_> =
+                scripts.iter().map(|s| s.full_name()).collect();
+            warnings.push((span, format!(
+                "'{}' mixes {} scripts", name, scripts.join(" and "),
+            )));
+        }
+    }
+}
+
+/// A skip instruction skips one word, so if the statement after it
+/// expands to more than one, only the first is skipped. If it expands
+/// to just one, like `banksel`, that word is skipped rather than the
+/// operation it was setting up for.
+fn skip_hazards(tr_unit: &TrUnit, warnings: &mut Vec<(Span, String)>) {
+    let stmts = tr_unit.stmts();
+    for (i, stmt) in stmts.iter().enumerate() {
+        let m = stmt.insn.desc.mnemonic;
+        if stmt.insn.desc.flow != Flow::Skip {
+            continue;
+        }
+        // Words from the same line as the skip are part of the same
+        // expansion, and were put there on purpose.
+        let next = match stmts.get(i + 1) {
+            Some(next) if next.span != stmt.span => next,
+            _ => continue,
+        };
+        let words = stmts[i + 1..].iter()
+            .take_while(|later| later.span == next.span)
+            .count();
+        if words > 1 {
+            warnings.push((stmt.span, format!(
+                "{} only skips the first of the {} words that line {} \
+                    assembles to",
+                m, words, next.span.line,
+            )));
+        } else if next.expanded {
+            warnings.push((stmt.span, format!(
+                "{} only skips the {} that line {} assembles to, not the \
+                    line after it",
+                m, next.insn.desc.mnemonic, next.span.line,
+            )));
+        }
+    }
+}
+
+/// Stack analysis can't follow `callw` or `brw` without a `targets`
+/// annotation, so say what it assumed instead.
+fn unannotated_indirect(
+    tr_unit: &TrUnit,
+    warnings: &mut Vec<(Span, String)>,
+) {
+    for stmt in tr_unit.stmts() {
+        if !tr_unit.indirect_targets(stmt.addr).is_empty() {
+            continue;
+        }
+        let assumed = match stmt.insn.desc.flow {
+            Flow::IndirectCall => "calls nothing",
+            Flow::IndirectBranch => "falls through",
+            _ => continue,
+        };
+        warnings.push((stmt.span, format!(
+            "{} has no targets annotation, so stack analysis assumes it {}",
+            stmt.insn.desc.mnemonic, assumed,
+        )));
+    }
+}
+
+/// One warning for each run of instructions that nothing gets to. Tables
+/// and other data aren't code, so they don't count.
+fn unreachable(
+    tr_unit: &TrUnit,
+    cfg: &Cfg,
+    warnings: &mut Vec<(Span, String)>,
+) {
+    let stmts = tr_unit.stmts();
+    let is_dead = |i: usize| {
+        !cfg.reachable[i] && !tr_unit.in_table(i as u16)
+            && stmts[i].insn.desc.mnemonic != "_data_"
+    };
+    let mut i = 0;
+    while i < stmts.len() {
+        if !is_dead(i) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < stmts.len() && is_dead(i) {
+            i += 1;
+        }
+        let words = i - start;
+        warnings.push((stmts[start].span, format!(
+            "unreachable code ({} word{})",
+            words,
+            if words == 1 { "" } else { "s" },
+        )));
+    }
+}
+
+/// Named labels that nothing refers to, other than at the vectors.
+fn unused_labels(
+    tr_unit: &TrUnit,
+    cfg: &Cfg,
+    warnings: &mut Vec<(Span, String)>,
+) {
+    let used: BTreeSet<_> =
+        tr_unit.refs().into_iter().map(|(name, _)| name).collect();
+
+    for stmt in tr_unit.stmts() {
+        if cfg.vectors.contains(&stmt.addr) {
+            continue;
+        }
+        for &(label, span) in &stmt.labels {
+            match stmt.symbol_name(label) {
+                Some(ref name) if !used.contains(name) => warnings.push(
+                    (span, format!("label '{}' is never used", name)),
+                ),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Code that runs on into a `dt` or `da` table.
+fn falls_into_tables(
+    tr_unit: &TrUnit,
+    cfg: &Cfg,
+    warnings: &mut Vec<(Span, String)>,
+) {
+    let stmts = tr_unit.stmts();
+    for (i, stmt) in stmts.iter().enumerate() {
+        if cfg.reachable[i] && cfg.falls_through[i]
+            && tr_unit.tables.contains_key(&(stmt.addr + 1))
+        {
+            warnings.push((stmt.span, format!(
+                "falls through into the table on line {}",
+                stmts[i + 1].span.line,
+            )));
+        }
+    }
+}
+
+/// Routines, and the code at the vectors, that can run on past their end
+/// into the next routine or off the end of the program. Jumping to another
+/// routine is fine; that's a tail call.
+fn runs_past_end(
+    tr_unit: &TrUnit,
+    cfg: &Cfg,
+    warnings: &mut Vec<(Span, String)>,
+) {
+    let stmts = tr_unit.stmts();
+    let entries = cfg.entries();
+    for &entry in &entries {
+        let mut seen = BTreeSet::new();
+        let mut todo = vec![entry];
+        while let Some(addr) = todo.pop() {
+            let i = addr as usize;
+            if i >= stmts.len() || !seen.insert(addr) {
+                continue;
+            }
+            let past_end = if !cfg.falls_through[i] {
+                None
+            } else if i + 1 == stmts.len() {
+                Some("run off the end of the program".to_string())
+            } else if entries.contains(&(addr + 1)) {
+                Some(format!(
+                    "run on into {} without returning",
+                    tr_unit.addr_name(addr + 1),
+                ))
+            } else {
+                None
+            };
+            if let Some(past_end) = past_end {
+                warnings.push((stmts[i].span, format!(
+                    "{} can {}",
+                    tr_unit.addr_name(entry), past_end,
+                )));
+                break;
+            }
+            todo.extend(cfg.next[i].iter().filter(|&next| {
+                !entries.contains(next)
+            }));
+        }
+    }
+}
+
+/// The SFR `stmt`'s register operand is, if it's one we know.
+fn sfr(tr_unit: &TrUnit, stmt: &Stmt) -> Option<&'static Sfr> {
+    device::sfr(tr_unit.device, *tr_unit.regs.get(&stmt.addr)?)
+}
+
+/// Read-modify-write instructions on a port read the pins rather than what
+/// was last written to them, so they can change other pins' outputs.
+fn rmw_ports(tr_unit: &TrUnit, warnings: &mut Vec<(Span, String)>) {
+    for stmt in tr_unit.stmts() {
+        if !(stmt.insn.reads_f() && stmt.insn.writes_f()) {
+            continue;
+        }
+        let sfr = match sfr(tr_unit, stmt) {
+            Some(sfr) => sfr,
+            None => continue,
+        };
+        if let Some(latch) = sfr.latch {
+            warnings.push((stmt.span, format!(
+                "{} on {} reads the pins, not the output latch; use {}",
+                stmt.insn.desc.mnemonic, sfr.name, latch,
+            )));
+        }
+    }
+}
+
+fn read_only_writes(tr_unit: &TrUnit, warnings: &mut Vec<(Span, String)>) {
+    for stmt in tr_unit.stmts() {
+        if !stmt.insn.writes_f() {
+            continue;
+        }
+        match sfr(tr_unit, stmt) {
+            Some(sfr) if sfr.read_only => warnings.push((stmt.span, format!(
+                "{} writes to {}, which is read-only",
+                stmt.insn.desc.mnemonic, sfr.name,
+            ))),
+            _ => (),
+        }
+    }
+}
+
+/// Whether PCLATH is set on every way into each address: every
+/// reachable jump, fall-through or call that gets there. Nothing has set it
+/// at the vectors.
+fn pclath_set(tr_unit: &TrUnit, cfg: &Cfg) -> Vec<bool> {
+    let stmts = tr_unit.stmts();
+    let sets = |i: usize| {
+        let insn = &stmts[i].insn;
+        insn.desc.mnemonic == "movlp"
+            || insn.writes_f() && insn.operands[0].raw & 0x7F == 0x0A
+    };
+    let mut preds = vec![vec![]; stmts.len()];
+    for (i, (next, calls)) in cfg.next.iter().zip(&cfg.calls).enumerate() {
+        if !cfg.reachable[i] {
+            continue;
+        }
+        for &to in next.iter().chain(calls) {
+            if let Some(preds) = preds.get_mut(to as usize) {
+                preds.push(i);
+            }
+        }
+    }
+
+    let mut set = vec![true; stmts.len()];
+    for &addr in &cfg.vectors {
+        if let Some(set) = set.get_mut(addr as usize) {
+            *set = false;
+        }
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..stmts.len() {
+            if set[i] && preds[i].iter().any(|&p| !set[p] && !sets(p)) {
+                set[i] = false;
+                changed = true;
+            }
+        }
+    }
+    set
+}
+
+/// Writing PCL also loads the top of the PC from PCLATH, so PCLATH should
+/// be set on the way there, or before every call to the routine it's in.
+fn pcl_writes(
+    tr_unit: &TrUnit,
+    cfg: &Cfg,
+    warnings: &mut Vec<(Span, String)>,
+) {
+    let set = pclath_set(tr_unit, cfg);
+    for (stmt, &set) in tr_unit.stmts().iter().zip(&set) {
+        let insn = &stmt.insn;
+        if !(insn.writes_f() && insn.operands[0].raw & 0x7F == 0x02) {
+            continue;
+        }
+        if !set {
+            warnings.push((stmt.span, format!(
+                "{} writes PCL without setting PCLATH first",
+                insn.desc.mnemonic,
+            )));
+        }
+    }
+}
+
+/// Bit instructions aside, writing STATUS clobbers C, DC and Z.
+fn status_writes(tr_unit: &TrUnit, warnings: &mut Vec<(Span, String)>) {
+    for stmt in tr_unit.stmts() {
+        let m = stmt.insn.desc.mnemonic;
+        if stmt.insn.writes_f() && stmt.insn.operands[0].raw & 0x7F == 0x03
+            && m != "bcf" && m != "bsf"
+        {
+            warnings.push((stmt.span, format!(
+                "{} to STATUS overwrites the C, DC and Z flags", m,
+            )));
+        }
+    }
+}
+
+/// `f, d` instructions write back to `f` unless told otherwise, which is
+/// easy to do by accident.
+fn default_dests(tr_unit: &TrUnit, warnings: &mut Vec<(Span, String)>) {
+    for stmt in tr_unit.stmts() {
+        if stmt.opds.iter().any(|opd| matches!(*opd, OpdSrc::Dest(None))) {
+            warnings.push((stmt.span, format!(
+                "{} has no destination, so it writes back to the register; \
+                    add ', F' or ', W'",
+                stmt.insn.desc.mnemonic,
+            )));
+        }
+    }
+}
+
+/// A result put in W that the next instruction replaces, flags and all,
+/// without looking at it. Skips are used for the skip, so they don't count.
+fn overwritten_w(tr_unit: &TrUnit, warnings: &mut Vec<(Span, String)>) {
+    let stmts = tr_unit.stmts();
+    for (stmt, next) in stmts.iter().zip(stmts.iter().skip(1)) {
+        let (insn, after) = (&stmt.insn, &next.insn);
+        let dead = insn.desc.flow == Flow::Fallthrough
+            && insn.desc.writes == Regs::D && insn.writes_w()
+            && after.writes_w() && !after.reads_w()
+            && insn.desc.status.iter()
+                .all(|bit| after.desc.status.contains(bit));
+        if dead {
+            warnings.push((stmt.span, format!(
+                "{}'s result in W is overwritten by the {} on line {} \
+                    before it's used",
+                insn.desc.mnemonic, after.desc.mnemonic, next.span.line,
+            )));
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_mixed_script() {
+    let tr_unit = ::build_tr_unit("\
+счётчик:
+    goto lооp_2 # Cyrillic 'о's
+lооp_2:
+    goto カウンタ値
+カウンタ値:
+    goto 한글カナ
+한글カナ:
+    goto счётчик
+", "test.asm").unwrap();
+    assert_eq!(tr_unit.warnings, vec![
+        (
+            Span { line: 2, col: 5 },
+            "'lооp_2' mixes Cyrillic and Latin scripts [mixed_script]"
+                .to_string(),
+        ),
+        (
+            Span { line: 6, col: 5 },
+            "'한글カナ' mixes Hangul and Katakana scripts [mixed_script]"
+                .to_string(),
+        ),
+    ]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_skip_hazards() {
+    let tr_unit = ::build_tr_unit("\
+flags equ 0x70
+    if_set flags, 1
+    endif
+    decfsz flags, F
+    if_set flags, 2
+    endif
+    btfsc flags, 0
+    lfsr FSR0, 0x2000
+    incfsz flags, F
+    nop
+    btfss flags, 0
+    banksel flags
+    clrf flags
+", "test.asm").unwrap();
+    let mut warnings = vec![];
+    skip_hazards(&tr_unit, &mut warnings);
+    assert_eq!(warnings, vec![
+        (
+            Span { line: 4, col: 5 },
+            "decfsz only skips the first of the 2 words that line 5 \
+                assembles to".to_string(),
+        ),
+        (
+            Span { line: 7, col: 5 },
+            "btfsc only skips the first of the 4 words that line 8 \
+                assembles to".to_string(),
+        ),
+        (
+            Span { line: 11, col: 5 },
+            "btfss only skips the movlb that line 12 assembles to, not the \
+                line after it".to_string(),
+        ),
+    ]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_flow() {
+    let tr_unit = ::build_tr_unit("\
+main:
+    call sub
+    bra $
+dead:
+    nop
+sub:
+    movlw 1
+    dt \"\"
+    call other
+    return
+table:
+    dt 1, 2
+other:
+    movlw 2
+    dt 3
+", "test.asm").unwrap();
+    let span = |line, col| Span { line, col };
+    assert_eq!(tr_unit.warnings, vec![
+        (span(4, 1), "label 'dead' is never used [unused_label]".to_string()),
+        (span(5, 5), "unreachable code (1 word) [unreachable]".to_string()),
+        (
+            span(11, 1),
+            "label 'table' is never used [unused_label]".to_string(),
+        ),
+        (
+            span(14, 5),
+            "falls through into the table on line 15 [falls_into_table]"
+                .to_string(),
+        ),
+    ]);
+
+    let tr_unit = ::build_tr_unit("\
+main:
+    call sub
+    bra $
+sub:
+    movlw 1
+", "test.asm").unwrap();
+    assert!(tr_unit.warnings.contains(&(
+        span(5, 5),
+        "sub can run off the end of the program [runs_past_end]".to_string(),
+    )));
+}
+
+#[cfg(test)]
+#[test]
+fn test_registers() {
+    let tr_unit = ::build_tr_unit("\
+device PIC16F1829
+    bsf 0x00C, 0 # PORTA
+    bsf 0x10C, 0 # LATA
+    movwf 0x199 # RCREG
+    movwf 0x003 # STATUS
+    bsf 0x003, 0
+    call table
+    movlp 0
+    goto jump
+jump:
+    addwf 0x002, F # PCL, but PCLATH is set on the only way here
+    retlw 1
+table:
+    addwf 0x002, F # and here it isn't, before the call
+    retlw 2
+", "test.asm").unwrap();
+    let lines: Vec<_> = tr_unit.warnings.iter()
+        .map(|&(span, ref msg)| (span.line, &msg[..]))
+        .collect();
+    assert_eq!(lines, vec![
+        (
+            2,
+            "bsf on PORTA reads the pins, not the output latch; use LATA \
+                [rmw_port]",
+        ),
+        (4, "movwf writes to RCREG, which is read-only [read_only_write]"),
+        (5, "movwf to STATUS overwrites the C, DC and Z flags [status_write]"),
+        (14, "addwf writes PCL without setting PCLATH first [pcl_write]"),
+    ]);
+
+    assert_eq!(nolint("# nolint"), Ok(Some(vec![])));
+    assert_eq!(
+        nolint("#nolint(rmw_port, pcl_write)"),
+        Ok(Some(vec!["rmw_port", "pcl_write"])),
+    );
+    assert_eq!(nolint("# no lint here"), Ok(None));
+    assert!(nolint("# nolint(typo)").is_err());
+    assert!(setting("rmw_port", "forbid").is_err());
+
+    assert_eq!(
+        ::build_tr_unit("\
+device PIC16F1829
+lint read_only_write = deny, status_write = deny
+    movwf 0x199 # nolint(read_only_write)
+    movwf 0x003
+", "test.asm").unwrap_err(),
+        "line 4: movwf to STATUS overwrites the C, DC and Z flags \
+            [status_write]",
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_destinations() {
+    let tr_unit = ::build_tr_unit("\
+lint pedantic = warn
+    decf 0x20
+    movf 0x20, W # only sets Z for the next one to set again
+    movf 0x20, W # still needed for Z
+    movlw 0
+    swapf 0x20, W
+    movf 0x20, W
+    bra $
+", "test.asm").unwrap();
+    assert_eq!(tr_unit.warnings, vec![
+        (
+            Span { line: 2, col: 5 },
+            "decf has no destination, so it writes back to the register; \
+                add ', F' or ', W' [default_dest]".to_string(),
+        ),
+        (
+            Span { line: 3, col: 5 },
+            "movf's result in W is overwritten by the movf on line 4 \
+                before it's used [overwritten_w]".to_string(),
+        ),
+        (
+            Span { line: 6, col: 5 },
+            "swapf's result in W is overwritten by the movf on line 7 \
+                before it's used [overwritten_w]".to_string(),
+        ),
+    ]);
+
+    assert!(covers("pedantic", "overwritten_w"));
+    assert!(!covers("pedantic", "default_dest"));
+    assert_eq!(setting("pedantic", "warn"), Ok(Level::Warn));
+}
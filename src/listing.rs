@@ -0,0 +1,101 @@
+use cycles;
+use stack;
+use std::fmt::Write;
+use {OpdSrc, TrUnit};
+
+/// Renders one row per source line, with the program address and encoded
+/// word of the statement that ends on that line, followed by the symbol
+/// table and a memory usage summary.
+pub(crate) fn listing(tr_unit: &TrUnit, input: &str) -> String {
+    let mut out = String::new();
+    let mut stmts = tr_unit.stmts().iter().peekable();
+
+    writeln!(out, "LINE  ADDR  WORD  SOURCE").unwrap();
+    for (i, line) in input.lines().enumerate() {
+        let line_num = i + 1;
+        let mut first = true;
+        while let Some(stmt) =
+            stmts.peek().filter(|stmt| stmt.span.line == line_num)
+        {
+            if tr_unit.in_gap(stmt.addr) {
+                stmts.next();
+                continue;
+            }
+            // Words after the first are from a pseudo-instruction expansion.
+            if first {
+                write!(out, "{:4}", line_num).unwrap();
+            } else {
+                write!(out, "    ").unwrap();
+            }
+            write!(out, "  {:04X}  {:04X}  ", stmt.addr, stmt.insn.encode())
+                .unwrap();
+            if first {
+                writeln!(out, "{}", line).unwrap();
+            } else {
+                // Operands written as expressions, like the labels block
+                // pseudo-ops branch to, read better than their values.
+                let opds: Vec<_> = stmt.insn.operands
+                    .iter()
+                    .zip(&stmt.opds)
+                    .map(|(opd, src)| match *src {
+                        OpdSrc::Expr(expr) => expr.to_string(),
+                        _ => format!("0x{:X}", opd.raw),
+                    })
+                    .collect();
+                let text = format!("{:?} {}", stmt.insn.desc, opds.join(", "));
+                writeln!(out, "    {}", text.trim_end()).unwrap();
+            }
+            first = false;
+            stmts.next();
+        }
+        if first {
+            writeln!(out, "{:4}              {}", line_num, line).unwrap();
+        }
+    }
+
+    writeln!(out).unwrap();
+    writeln!(out, "SYMBOL                            VALUE").unwrap();
+    for (label, addr) in tr_unit.labels() {
+        writeln!(out, "{:32}  {:04X}", label, addr).unwrap();
+    }
+    for (name, addr, _, _) in tr_unit.variables() {
+        writeln!(out, "{:32}  {:04X}", name, addr).unwrap();
+    }
+
+    writeln!(out).unwrap();
+    writeln!(out, "Program memory words used: {}", tr_unit.words_used())
+        .unwrap();
+    writeln!(out, "Data memory bytes used: {}", tr_unit.ram_used()).unwrap();
+    // Recursion would have failed the build, so this always works.
+    if let Ok(report) = stack::analyze(tr_unit) {
+        for line in report.lines(tr_unit) {
+            writeln!(out, "{}", line).unwrap();
+        }
+    }
+    for line in cycles::lines(tr_unit) {
+        writeln!(out, "{}", line).unwrap();
+    }
+    for &(line, cycles, error) in &tr_unit.delays {
+        write!(out, "Delay on line {}: {} cycles", line, cycles).unwrap();
+        match error {
+            Some(error) => writeln!(out, ", off by {:+.1} ns", error),
+            None => writeln!(out),
+        }.unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+#[test]
+fn test_listing() {
+    let input = "# blink\nstart: movlw 0\n";
+    let tr_unit = ::build_tr_unit(input, "test.asm").unwrap();
+
+    let lst = listing(&tr_unit, input);
+    let mut lines = lst.lines().skip(1);
+    assert_eq!(lines.next().unwrap(), "   1              # blink");
+    assert_eq!(lines.next().unwrap(), "   2  0000  3000  start: movlw 0");
+    assert!(lst.contains("start                             0000"));
+    assert!(lst.contains("Program memory words used: 1"));
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use {Span, TrUnit};
+
+struct Symbol<'s> {
+    value: i64,
+    section: &'s str,
+    def: Span,
+    refs: Vec<Span>,
+}
+
+/// Renders every symbol with its value, section, definition site and the
+/// lines that refer to it: labels (in `code`), data sections and their
+/// variables, and constants (in `const`). Names that are referred to but
+/// never defined are listed last.
+pub(crate) fn map(tr_unit: &TrUnit, path: &str) -> String {
+    let mut syms = BTreeMap::new();
+    for stmt in tr_unit.stmts() {
+        for &(label, span) in &stmt.labels {
+            let name = match stmt.symbol_name(label) {
+                Some(name) => name,
+                None => continue, // anonymous
+            };
+            syms.insert(name, Symbol {
+                value: i64::from(stmt.addr),
+                section: "code",
+                def: span,
+                refs: vec![],
+            });
+        }
+    }
+    for sec in tr_unit.data_sections.iter().filter(|sec| !sec.cblock) {
+        syms.insert(sec.name.to_string(), Symbol {
+            value: i64::from(sec.addr.unwrap_or(0)),
+            section: sec.name,
+            def: sec.span,
+            refs: vec![],
+        });
+    }
+    for (name, addr, section, span) in tr_unit.variables() {
+        syms.insert(name.to_string(), Symbol {
+            value: i64::from(addr),
+            section,
+            def: span,
+            refs: vec![],
+        });
+    }
+    for (&name, &value) in &tr_unit.constants.values {
+        syms.insert(name.to_string(), Symbol {
+            value,
+            section: "const",
+            def: tr_unit.constant_defs[name],
+            refs: vec![],
+        });
+    }
+
+    let mut undefined: BTreeMap<String, Vec<Span>> = BTreeMap::new();
+    for (name, span) in tr_unit.refs() {
+        match syms.get_mut(&name) {
+            Some(sym) => sym.refs.push(span),
+            None => undefined.entry(name).or_default().push(span),
+        }
+    }
+
+    let loc = |span: &Span| format!("{}:{}", path, span.line);
+    let locs = |spans: &[Span]| {
+        spans.iter().map(&loc).collect::<Vec<_>>().join(", ")
+    };
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:32}  {:5}  {:7}  {:24}  REFERENCED",
+        "SYMBOL", "VALUE", "SECTION", "DEFINED",
+    ).unwrap();
+    for (name, sym) in &syms {
+        let value = if sym.value < 0 {
+            format!("-{:X}", -sym.value)
+        } else {
+            format!("{:04X}", sym.value)
+        };
+        writeln!(
+            out,
+            "{:32}  {:5}  {:7}  {:24}  {}",
+            name, value, sym.section, loc(&sym.def), locs(&sym.refs),
+        ).unwrap();
+    }
+    for (name, refs) in &undefined {
+        writeln!(
+            out,
+            "{:32}  ????   {:7}  {:24}  {}",
+            name, "", "(undefined)", locs(refs),
+        ).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+#[test]
+fn test_map() {
+    let tr_unit = ::build_tr_unit("\
+LIMIT equ -3
+SIZE equ 2
+ORIGIN equ SIZE + 1
+device PIC16F1829
+udata vars
+count res SIZE
+    org ORIGIN
+start:
+    decf count, F
+    goto start
+    movlw LIMIT
+    assert start == ORIGIN
+", "a.asm").unwrap();
+
+    let map = map(&tr_unit, "a.asm");
+    let lines: Vec<Vec<_>> = map.lines().skip(1)
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(lines, vec![
+        vec!["LIMIT", "-3", "const", "a.asm:1", "a.asm:11"],
+        vec!["ORIGIN", "0003", "const", "a.asm:3", "a.asm:7,", "a.asm:12"],
+        vec!["SIZE", "0002", "const", "a.asm:2", "a.asm:3,", "a.asm:6"],
+        vec!["count", "0020", "vars", "a.asm:6", "a.asm:9"],
+        vec!["start", "0003", "code", "a.asm:8", "a.asm:10,", "a.asm:12"],
+        vec!["vars", "0020", "vars", "a.asm:5"],
+    ]);
+}
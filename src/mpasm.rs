@@ -0,0 +1,484 @@
+//! MPASM (and gpasm) source compatibility. `translate` rewrites MPASM
+//! source into our syntax one line at a time, so line numbers in errors,
+//! listings and debug info still point into the original file.
+//!
+//! `#define`s are substituted here, as whole words outside quotes. A
+//! `cblock` without an address is placed by the data allocator rather
+//! than following on from the previous one.
+//!
+//! A file asks for this with a `syntax mpasm` line before any code (see
+//! `selected`), or the caller can ask for it regardless.
+
+use data::{OpdDescKind, INSN_DESCS};
+
+/// Mnemonics and directives that aren't in `INSN_DESCS` but mean the same
+/// thing in both syntaxes.
+static OTHER_OPS: &[&str] = &[
+    "banksel", "lfsr", "dt", "da", "de", "tris", "moviw", "movwi", "org",
+    "cblock", "endc",
+];
+
+/// MPASM directives that don't affect the output.
+static IGNORED: &[&str] = &["errorlevel", "nolist", "title", "subtitle"];
+
+/// Directives handled here, which can start in the first column like a
+/// label.
+static DIRECTIVES: &[&str] = &[
+    "end", "radix", "list", "processor", "__config", "include", "udata",
+    "udata_shr", "syntax",
+];
+
+fn is_op(word: &str) -> bool {
+    let word = word.to_lowercase();
+    OTHER_OPS.contains(&&word[..])
+        || INSN_DESCS.iter().any(|desc| desc.mnemonic == word)
+}
+
+fn is_directive(word: &str) -> bool {
+    let word = word.to_lowercase();
+    DIRECTIVES.contains(&&word[..]) || IGNORED.contains(&&word[..])
+}
+
+/// Whether `input` asks to be read as MPASM: its first line that isn't
+/// blank or a `;` comment is `syntax mpasm`.
+pub(crate) fn selected(input: &str) -> bool {
+    input.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with(';'))
+        .is_some_and(|line| {
+            let words: Vec<_> = line.split_whitespace()
+                .take_while(|word| !word.starts_with(';'))
+                .map(str::to_lowercase)
+                .collect();
+            words == ["syntax", "mpasm"]
+        })
+}
+
+/// Whether `m` takes an `f, d` pair.
+fn has_dest(m: &str) -> bool {
+    INSN_DESCS.iter()
+        .filter(|desc| desc.mnemonic == m)
+        .flat_map(|desc| desc.operands)
+        .any(|opd| matches!(opd.kind, OpdDescKind::D))
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Length of the quoted literal at the start of `s`, quotes included.
+fn quoted_len(s: &str) -> Result<usize, String> {
+    let quote = s.chars().next().unwrap();
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return Ok(i + 1);
+        }
+    }
+    Err(format!("unterminated {}", s))
+}
+
+/// Splits a line into code and the text of its `;` comment.
+pub(crate) fn split_comment(line: &str)
+    -> Result<(&str, Option<&str>), String>
+{
+    let mut i = 0;
+    while let Some(c) = line[i..].chars().next() {
+        match c {
+            ';' => return Ok((&line[..i], Some(&line[i + 1..]))),
+            '"' | '\'' => i += quoted_len(&line[i..])?,
+            _ => i += c.len_utf8(),
+        }
+    }
+    Ok((line, None))
+}
+
+fn parse_radix(name: &str) -> Result<u32, String> {
+    match &name.trim().to_lowercase()[..] {
+        "hex" => Ok(16),
+        "dec" => Ok(10),
+        "oct" => Ok(8),
+        _ => Err(format!("unknown radix '{}'", name.trim())),
+    }
+}
+
+/// Writes `digits` in `radix` as one of our integer literals.
+fn number(digits: &str, radix: u32) -> Result<String, String> {
+    let val = u32::from_str_radix(digits, radix)
+        .map_err(|_| format!("bad number '{}'", digits))?;
+    Ok(match radix {
+        2 => format!("0n{}", digits),
+        8 => format!("0c{}", digits),
+        16 => format!("0x{}", digits),
+        _ => val.to_string(),
+    })
+}
+
+/// Rewrites an MPASM expression, reading numbers in the default radix.
+pub(crate) fn expr(s: &str) -> Result<String, String> {
+    Translator::new().operands(s)
+}
+
+struct Translator {
+    /// For numbers without a radix; MPASM starts out in hex.
+    radix: u32,
+    /// Everything after `end` is ignored.
+    ended: bool,
+    /// Between `cblock` and `endc`
+    cblock: bool,
+    /// `#define` names and replacement text, latest last
+    defines: Vec<(String, String)>,
+}
+
+impl Translator {
+    fn new() -> Self {
+        Translator { radix: 16, ended: false, cblock: false, defines: vec![] }
+    }
+
+    /// Substitutes `#define`d names.
+    fn expand(&self, s: &str) -> Result<String, String> {
+        let mut out = String::new();
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            let len = if c == '"' || c == '\'' {
+                quoted_len(rest)?
+            } else if is_ident_char(c) {
+                rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len())
+            } else {
+                c.len_utf8()
+            };
+            let word = &rest[..len];
+            out.push_str(
+                self.defines.iter().rev()
+                    .find(|&(name, _)| name == word)
+                    .map_or(word, |(_, text)| text),
+            );
+            rest = &rest[len..];
+        }
+        Ok(out)
+    }
+
+    /// Rewrites the `name` and `name:size` entries of a `cblock` line.
+    fn cblock_vars(&self, code: &str) -> Result<String, String> {
+        let mut vars = vec![];
+        for var in code.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            vars.push(match var.find(':') {
+                Some(colon) => format!(
+                    "{}[{}]",
+                    var[..colon].trim_end(),
+                    self.operands(var[colon + 1..].trim_start())?,
+                ),
+                None => var.to_string(),
+            });
+        }
+        Ok(vars.join(", "))
+    }
+
+    /// Rewrites the numeric literals in an operand list.
+    fn operands(&self, s: &str) -> Result<String, String> {
+        let mut out = String::new();
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            let prev_is_ident = out.chars().last().is_some_and(is_ident_char);
+            let quoted_prefix = rest[c.len_utf8()..].starts_with('\'')
+                && "bBoOdDhHaA".contains(c)
+                && !prev_is_ident;
+            let len = if c == '"' || c == '\'' {
+                let len = quoted_len(rest)?;
+                out.push_str(&rest[..len]);
+                len
+            } else if quoted_prefix {
+                let len = 1 + quoted_len(&rest[1..])?;
+                let digits = &rest[2..len - 1];
+                out.push_str(&match c.to_ascii_lowercase() {
+                    'b' => number(digits, 2)?,
+                    'o' => number(digits, 8)?,
+                    'd' => number(digits, 10)?,
+                    'h' => number(digits, 16)?,
+                    _ => rest[1..len].to_string(),
+                });
+                len
+            } else if c == '.' && !prev_is_ident
+                && rest[1..].starts_with(|c: char| c.is_ascii_digit())
+            {
+                let len = 1 + rest[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len() - 1);
+                out.push_str(&number(&rest[1..len], 10)?);
+                len
+            } else if c.is_ascii_digit() && !prev_is_ident {
+                let len = rest.find(|c: char| !c.is_ascii_alphanumeric())
+                    .unwrap_or(rest.len());
+                let word = &rest[..len];
+                let lower = word.to_lowercase();
+                out.push_str(&if lower.starts_with("0x") {
+                    number(&word[2..], 16)?
+                } else if lower.ends_with('h') {
+                    number(&word[..len - 1], 16)?
+                } else {
+                    number(word, self.radix)?
+                });
+                len
+            } else if is_ident_char(c) {
+                let len = rest.find(|c: char| !is_ident_char(c))
+                    .unwrap_or(rest.len());
+                out.push_str(&rest[..len]);
+                len
+            } else {
+                out.push(c);
+                c.len_utf8()
+            };
+            rest = &rest[len..];
+        }
+        Ok(out)
+    }
+
+    /// Translates the code part of a line, leaving off the comment.
+    fn code(&mut self, code: &str) -> Result<String, String> {
+        let mut words = code.split_whitespace();
+        let first = words.next().unwrap_or("").to_lowercase();
+        if first == "#define" {
+            let name = words.next().ok_or("#define needs a name")?;
+            let text = code.trim_start()[first.len()..].trim_start()
+                [name.len()..].trim();
+            let text = self.expand(text)?;
+            self.defines.push((name.to_string(), text));
+            return Ok(String::new());
+        }
+        let code = &self.expand(code)?;
+        if self.cblock && first != "endc" {
+            return self.cblock_vars(code);
+        }
+
+        // Anything that starts in the first column is a label, unless it's
+        // an instruction or directive.
+        let mut label = None;
+        let mut rest = &code[..];
+        if code.starts_with(is_ident_char) {
+            let end = code.find(|c: char| !is_ident_char(c))
+                .unwrap_or(code.len());
+            if !is_op(&code[..end]) && !is_directive(&code[..end]) {
+                label = Some(&code[..end]);
+                rest = code[end..].trim_start_matches(':');
+            }
+        }
+        let rest = rest.trim();
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let op = rest[..end].to_lowercase();
+        let args = rest[end..].trim();
+        let label_colon = label.map_or(String::new(), |l| format!("{}: ", l));
+
+        Ok(match &op[..] {
+            "" => label_colon,
+            "end" => {
+                self.ended = true;
+                label_colon
+            },
+            "radix" => {
+                self.radix = parse_radix(args)?;
+                label_colon
+            },
+            "syntax" if args.eq_ignore_ascii_case("mpasm") => label_colon,
+            "equ" => format!(
+                "{} equ {}",
+                label.ok_or("EQU needs a name")?,
+                self.operands(args)?,
+            ),
+            "cblock" | "endc" => {
+                self.cblock = op == "cblock";
+                format!("{}{} {}", label_colon, op, self.operands(args)?)
+            },
+            "list" | "processor" => {
+                let mut device = None;
+                for opt in args.split(',') {
+                    let opt = opt.trim();
+                    let lower = opt.to_lowercase();
+                    if op == "processor" {
+                        device = Some(opt);
+                    } else if lower.starts_with("p=") {
+                        device = Some(&opt[2..]);
+                    } else if lower.starts_with("r=") {
+                        self.radix = parse_radix(&opt[2..])?;
+                    }
+                }
+                match device {
+                    Some(dev) if dev.to_lowercase().starts_with("pic") =>
+                        format!("{}device {}", label_colon, dev),
+                    Some(dev) => format!("{}device PIC{}", label_colon, dev),
+                    None => label_colon,
+                }
+            },
+            "__config" => {
+                if !args.contains(',') {
+                    return Err(
+                        "__CONFIG needs an address on this device".to_string(),
+                    );
+                }
+                format!("{}config {}", label_colon, self.operands(args)?)
+            },
+            "#include" | "include" => format!(
+                "{}include \"{}\"",
+                label_colon,
+                args.trim_matches(|c| c == '<' || c == '>' || c == '"'),
+            ),
+            "res" => format!(
+                "{} res {}",
+                label.ok_or("res needs a label")?,
+                self.operands(args)?,
+            ),
+            "udata" | "udata_shr" => {
+                if !args.is_empty() {
+                    return Err(format!(
+                        "{} at a fixed address isn't supported", op,
+                    ));
+                }
+                // MPASM's default names start with a '.', which we don't
+                // allow.
+                let default = format!("_{}", op);
+                format!("{} {}", op, label.unwrap_or(&default))
+            },
+            _ if IGNORED.contains(&&op[..]) => label_colon,
+            _ if has_dest(&op) && args.contains(',') => {
+                let comma = args.rfind(',').unwrap();
+                let dest = match &args[comma + 1..].trim().to_lowercase()[..] {
+                    "w" | "0" => "W",
+                    "f" | "1" => "F",
+                    dest => {
+                        return Err(format!("bad destination '{}'", dest));
+                    },
+                };
+                format!(
+                    "{}{} {}, {}",
+                    label_colon, op, self.operands(&args[..comma])?, dest,
+                )
+            },
+            _ if is_op(&op) => format!(
+                "{}{} {}", label_colon, op, self.operands(args)?,
+            ),
+            _ => {
+                return Err(format!(
+                    "MPASM directive or macro '{}' isn't supported", op,
+                ));
+            },
+        })
+    }
+}
+
+/// Rewrites MPASM source into our syntax. Lines stay where they are, with
+/// `;` comments turned into `#` comments.
+pub(crate) fn translate(input: &str) -> Result<String, String> {
+    let mut tr = Translator::new();
+    let mut out = String::new();
+    for (i, line) in input.lines().enumerate() {
+        let err = |msg: String| format!("line {}: {}", i + 1, msg);
+        if tr.ended {
+            out.push('\n');
+            continue;
+        }
+        let (code, comment) = split_comment(line).map_err(&err)?;
+        let indent = &code[..code.len() - code.trim_start().len()];
+        let code = tr.code(code).map_err(&err)?;
+        out.push_str(indent);
+        out.push_str(code.trim_end());
+        if let Some(comment) = comment {
+            if !code.is_empty() {
+                out.push(' ');
+            }
+            out.push('#');
+            out.push_str(comment);
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+#[test]
+fn test_translate() {
+    let src = "\
+\tlist p=16f1829, r=dec ; the usual
+#include <p16f1829.inc>
+\t__CONFIG 0x8007, h'3FE4' & b'11111111111111'
+\terrorlevel -302
+count\tres 1
+LIMIT\tEQU d'40' ; max
+#define\tSTEP 2 + LIMIT
+\tCBLOCK 0x20
+\t\tflags, buf:LIMIT
+\t\tw_save
+\tENDC
+\torg 0x10
+loop\tDECFSZ count,f
+\taddlw STEP
+\tMOVF\tcount, 0
+\tmovlw .10 + 10 + d'3'
+\tradix hex
+\tmovlw 10 + 0Fh + o'17' + A'x'
+\tdt \"a;b\", 'c'
+done:
+\tend
+\tthis is ignored
+";
+    let out = translate(src).unwrap();
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines, vec![
+        "\tdevice PIC16f1829 # the usual",
+        "include \"p16f1829.inc\"",
+        "\tconfig 0x8007, 0x3FE4 & 0n11111111111111",
+        "\t",
+        "count res 1",
+        "LIMIT equ 40 # max",
+        "",
+        "\tcblock 0x20",
+        "\t\tflags, buf[LIMIT]",
+        "\t\tw_save",
+        "\tendc",
+        "\torg 0x10",
+        "loop: decfsz count, F",
+        "\taddlw 2 + LIMIT",
+        "\tmovf count, W",
+        "\tmovlw 10 + 10 + 3",
+        "\t",
+        "\tmovlw 0x10 + 0x0F + 0c17 + 'x'",
+        "\tdt \"a;b\", 'c'",
+        "done:",
+        "\t",
+        "",
+    ]);
+
+    assert!(translate("\tmovf x, q").is_err());
+    assert!(translate("\tmovlw 12g").is_err());
+    assert!(translate("\tifdef X").unwrap_err().starts_with("line 1: "));
+    assert!(translate("\tEQU 3").is_err());
+
+    // Directives can start in the first column too.
+    let src = "\
+syntax mpasm
+LIST p=16f1829
+__CONFIG 0x8007, h'3FE4'
+radix dec
+errorlevel -302
+x\tmovlw 10
+end
+\tnop
+";
+    assert!(selected(src));
+    assert!(selected("; legacy code\n\n  SYNTAX mpasm ; please\n"));
+    assert!(!selected("\tnop\nsyntax mpasm\n"));
+    let out = translate(src).unwrap();
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines, vec![
+        "",
+        "device PIC16f1829",
+        "config 0x8007, 0x3FE4",
+        "",
+        "",
+        "x: movlw 10",
+        "",
+        "",
+    ]);
+}
@@ -0,0 +1,381 @@
+//! Pseudo-instructions, which assemble to one or more real instructions.
+
+use data::{InsnDesc, DATA_WORD_DESC, INSN_DESCS};
+use {expr, OpdSrc, Span, Stmt};
+
+pub(crate) fn desc(mnemonic: &str) -> &'static InsnDesc {
+    INSN_DESCS.iter().find(|desc| desc.mnemonic == mnemonic).unwrap()
+}
+
+/// FSRnL for "FSR0"/"FSR1"; FSRnH is the next register up.
+fn fsr_low(fsrn: &str) -> i64 {
+    match fsrn {
+        "FSR0" => 0x04,
+        "FSR1" => 0x06,
+        _ => unreachable!(),
+    }
+}
+
+/// Lowers pseudo-instruction `m`, taking its operands from the grammar
+/// captures via `cap`. Returns `None` if `m` is a real instruction.
+pub(crate) fn expand<'s, C>(m: &str, cap: C)
+    -> Option<Vec<(&'static InsnDesc, Vec<OpdSrc<'s>>)>>
+    where C: Fn(&str) -> Option<&'s str>
+{
+    Some(match m {
+        "banksel" => vec![
+            (desc("movlb"), vec![OpdSrc::BankOf(cap("k").unwrap())]),
+        ],
+        "lfsr" => {
+            let fsrl = fsr_low(cap("fsrn").unwrap());
+            let k = cap("k").unwrap();
+            vec![
+                (desc("movlw"), vec![OpdSrc::High(k)]),
+                (desc("movwf"), vec![OpdSrc::Const(fsrl + 1)]),
+                (desc("movlw"), vec![OpdSrc::Low(k)]),
+                (desc("movwf"), vec![OpdSrc::Const(fsrl)]),
+            ]
+        },
+        _ => return None,
+    })
+}
+
+/// Lowers a `dt` or `da` table. Each of `vals` is an `expr` or a `str`.
+/// `dt` makes a `retlw` per value or character; `da` packs two 7-bit
+/// characters into each word, high one first, padding with zero, so its
+/// characters have to be ASCII.
+pub(crate) fn table<'s>(m: &str, vals: &[&'s str])
+    -> Result<Vec<(&'static InsnDesc, Vec<OpdSrc<'s>>)>, String>
+{
+    let desc = if m == "dt" { desc("retlw") } else { &DATA_WORD_DESC };
+    let mut insns = vec![];
+    for &val in vals {
+        if !val.starts_with('"') {
+            insns.push((desc, vec![OpdSrc::Expr(val)]));
+            continue;
+        }
+        let max = if m == "dt" { 0xFF } else { 0x7F };
+        let chars: Vec<_> = expr::unescape(val).chars()
+            .map(|c| if c as u32 > max {
+                Err(format!(
+                    "{} can't hold '{}' (over 0x{:X})",
+                    m, c.escape_debug(), max,
+                ))
+            } else {
+                Ok(c as i64)
+            })
+            .collect::<Result<_, _>>()?;
+        if m == "dt" {
+            for c in chars {
+                insns.push((desc, vec![OpdSrc::Const(c)]));
+            }
+        } else {
+            for pair in chars.chunks(2) {
+                let word = pair[0] << 7 | pair.get(1).cloned().unwrap_or(0);
+                insns.push((desc, vec![OpdSrc::Const(word)]));
+            }
+        }
+    }
+    Ok(insns)
+}
+
+pub(crate) type Insns<'s> = Vec<(&'static InsnDesc, Vec<OpdSrc<'s>>)>;
+
+/// An open block, with the label its generated labels start with. Indexes
+/// are of the `goto` that leaves the block, which is filled in when the
+/// block is closed.
+enum Block<'s> {
+    If { name: &'s str, skip: usize },
+    Else { name: &'s str, skip: usize },
+    While { name: &'s str, top: u16, exit: usize },
+    Loop { name: &'s str, top: u16, count: &'s str },
+}
+
+/// A generated label. It has to outlive the source it's mixed in with, so
+/// it's leaked like an included header is.
+fn label(name: String) -> &'static str {
+    Box::leak(name.into_boxed_str())
+}
+
+/// Checks that a `goto` at `from` can reach `name`, at `to`: it only
+/// holds the low 11 bits of the address, and the rest comes from PCLATH,
+/// which the block doesn't touch.
+fn reach(from: u16, to: u16, name: &str) -> Result<(), String> {
+    if from >> 11 == to >> 11 {
+        Ok(())
+    } else {
+        Err(format!(
+            "the goto at 0x{:X} can't reach {} at 0x{:X}, on another 2K page",
+            from, name, to,
+        ))
+    }
+}
+
+/// Whether `m` opens, continues or closes a block (see `Blocks`).
+pub(crate) fn is_block_op(m: &str) -> bool {
+    [
+        "if_set", "if_clr", "else", "endif", "while_set", "while_clr",
+        "endw", "loop_decfsz", "endl",
+    ].contains(&m)
+}
+
+/// Back to `top` from a branch at `addr`: `bra` if it reaches, else `goto`.
+pub(crate) fn branch_back<'s>(top: u16, addr: u16)
+    -> (&'static InsnDesc, Vec<OpdSrc<'s>>)
+{
+    let m = if addr + 1 - top <= 256 { "bra" } else { "goto" };
+    (desc(m), vec![OpdSrc::Const(i64::from(top))])
+}
+
+/// Lowers the structured control-flow pseudo-ops:
+///
+/// - `if_set f, b` ... [`else` ...] `endif` runs the first part if bit `b`
+///   of `f` is set and the `else` part if not. `if_clr` is the opposite.
+/// - `while_set f, b` ... `endw` repeats while the bit is set, checking
+///   first. `while_clr` is the opposite.
+/// - `loop_decfsz f` ... `endl` runs the body, decrements `f` and repeats
+///   until it reaches zero.
+///
+/// Forward branches are `goto`s whose targets are filled in when the block
+/// closes. Backward ones are `bra` when it reaches. Either way they go to
+/// generated labels, like `_if1_else` and `_while2_end`, so the listing and
+/// symbol tables can name them.
+#[derive(Default)]
+pub(crate) struct Blocks<'s> {
+    /// (block, mnemonic that opened it, line)
+    open: Vec<(Block<'s>, &'s str, usize)>,
+    /// How many blocks have been opened, to number their labels
+    count: usize,
+    /// (label, span, address) for `finish` to attach
+    labels: Vec<(&'s str, Span, u16)>,
+}
+
+impl<'s> Blocks<'s> {
+    /// `m` is at `span`, `addr` is where the first word will go and
+    /// `stmts` is everything before it. Labels the words use go in `refs`.
+    pub(crate) fn lower<C>(
+        &mut self,
+        m: &'s str,
+        cap: C,
+        span: Span,
+        addr: u16,
+        stmts: &mut [Stmt<'s>],
+        refs: &mut Vec<&'s str>,
+    ) -> Result<Insns<'s>, String>
+        where C: Fn(&str) -> Option<&'s str>
+    {
+        let line = span.line;
+        let next = stmts.len();
+        let labels = &mut self.labels;
+        let mut define = |name: &'s str, at: u16| {
+            labels.push((name, span, at));
+        };
+        let mut patch = |i: usize, name: &'s str, target: u16| {
+            reach(stmts[i].addr, target, name)?;
+            stmts[i].opds[0] = OpdSrc::Expr(name);
+            stmts[i].refs.push(name);
+            Ok::<_, String>(())
+        };
+        let mut back = |top: u16, at: u16, name: &'s str| {
+            let (desc, _) = branch_back(top, at);
+            if desc.mnemonic == "goto" {
+                reach(at, top, name)?;
+            }
+            refs.push(name);
+            Ok::<_, String>((desc, vec![OpdSrc::Expr(name)]))
+        };
+        let goto = || (desc("goto"), vec![OpdSrc::Const(0)]);
+        let test = |set| {
+            let m = if set { "btfss" } else { "btfsc" };
+            (desc(m), OpdSrc::for_insn(desc(m), &cap))
+        };
+        let count = &mut self.count;
+        let mut open = |kind: &str| {
+            *count += 1;
+            label(format!("_{}{}", kind, count))
+        };
+
+        Ok(match m {
+            "if_set" | "if_clr" => {
+                let block = Block::If { name: open("if"), skip: next + 1 };
+                self.open.push((block, m, line));
+                vec![test(m == "if_set"), goto()]
+            },
+            "while_set" | "while_clr" => {
+                let name = open("while");
+                define(name, addr);
+                let block = Block::While { name, top: addr, exit: next + 1 };
+                self.open.push((block, m, line));
+                vec![test(m == "while_set"), goto()]
+            },
+            "loop_decfsz" => {
+                let name = open("loop");
+                define(name, addr);
+                let count = cap("f").unwrap();
+                let block = Block::Loop { name, top: addr, count };
+                self.open.push((block, m, line));
+                vec![]
+            },
+            _ => {
+                let (block, opener, opener_line) = self.open.pop()
+                    .ok_or_else(|| format!("{} without a block to close", m))?;
+                match (m, block) {
+                    ("else", Block::If { name, skip }) => {
+                        let target = label(format!("{}_else", name));
+                        patch(skip, target, addr + 1)?;
+                        define(target, addr + 1);
+                        let block = Block::Else { name, skip: next };
+                        self.open.push((block, opener, opener_line));
+                        vec![goto()]
+                    },
+                    ("endif", Block::If { name, skip })
+                    | ("endif", Block::Else { name, skip }) => {
+                        let target = label(format!("{}_end", name));
+                        patch(skip, target, addr)?;
+                        define(target, addr);
+                        vec![]
+                    },
+                    ("endw", Block::While { name, top, exit }) => {
+                        let target = label(format!("{}_end", name));
+                        patch(exit, target, addr + 1)?;
+                        define(target, addr + 1);
+                        vec![back(top, addr, name)?]
+                    },
+                    ("endl", Block::Loop { name, top, count }) => vec![
+                        (desc("decfsz"), vec![
+                            OpdSrc::Expr(count), OpdSrc::Dest(Some("F")),
+                        ]),
+                        back(top, addr + 1, name)?,
+                    ],
+                    _ => return Err(format!(
+                        "{} doesn't match {} on line {}",
+                        m, opener, opener_line,
+                    )),
+                }
+            },
+        })
+    }
+
+    /// Checks that every block was closed, and hands back the generated
+    /// labels as (label, span, address).
+    pub(crate) fn finish(self) -> Result<Vec<(&'s str, Span, u16)>, String> {
+        match self.open.last() {
+            Some(&(_, opener, line)) => Err(format!(
+                "line {}: {} is never closed", line, opener,
+            )),
+            None => Ok(self.labels),
+        }
+    }
+}
+
+/// `moviw`/`movwi` with a pre/post increment or decrement.
+pub(crate) fn moviwwi_mm<'s, C>(m: &str, cap: C)
+    -> (&'static InsnDesc, Vec<OpdSrc<'s>>)
+    where C: Fn(&str) -> Option<&'s str>
+{
+    let fsrn = (cap("fsrn") == Some("FSR1")) as i64;
+    let mm = match (cap("pre"), cap("post")) {
+        (Some("++"), _) => 0b00,
+        (Some("--"), _) => 0b01,
+        (_, Some("++")) => 0b10,
+        (_, Some("--")) => 0b11,
+        _ => unreachable!(),
+    };
+    let desc = desc(if m == "moviw" { "moviw_mm" } else { "movwi_mm" });
+    (desc, vec![OpdSrc::Const(fsrn), OpdSrc::Const(mm)])
+}
+
+#[cfg(test)]
+#[test]
+fn test_expand() {
+    let cap = |name: &str| match name {
+        "fsrn" => Some("FSR1"),
+        "k" => Some("linear(buf)"),
+        "post" => Some("--"),
+        _ => None,
+    };
+    let lfsr = expand("lfsr", cap).unwrap();
+    let mnemonics: Vec<_> =
+        lfsr.iter().map(|&(desc, _)| desc.mnemonic).collect();
+    assert_eq!(mnemonics, vec!["movlw", "movwf", "movlw", "movwf"]);
+    match lfsr[1].1[0] {
+        OpdSrc::Const(0x07) => (),
+        ref opd => panic!("{:?}", opd),
+    }
+    assert!(expand("movlw", cap).is_none());
+
+    let dt = table("dt", &["\"a\\x01\"", "'b'"]).unwrap();
+    assert_eq!(dt.len(), 3);
+    assert_eq!(dt[0].0.mnemonic, "retlw");
+    match (&dt[1].1[0], &dt[2].1[0]) {
+        (&OpdSrc::Const(1), &OpdSrc::Expr("'b'")) => (),
+        opds => panic!("{:?}", opds),
+    }
+    let da = table("da", &["\"abc\""]).unwrap();
+    match (&da[0].1[0], &da[1].1[0]) {
+        (&OpdSrc::Const(0x30E2), &OpdSrc::Const(0x3180)) => (),
+        opds => panic!("{:?}", opds),
+    }
+
+    let (desc, opds) = moviwwi_mm("movwi", cap);
+    assert_eq!(desc.mnemonic, "movwi_mm");
+    match (opds[0], opds[1]) {
+        (OpdSrc::Const(1), OpdSrc::Const(0b11)) => (),
+        opds => panic!("{:?}", opds),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_blocks() {
+    let tr_unit = ::build_tr_unit("\
+flags equ 0x70
+    while_clr flags, 2
+    if_set flags, 2
+    else
+    loop_decfsz flags
+    endl
+    endif
+    endw
+", "test.asm").unwrap();
+    let words: Vec<_> =
+        tr_unit.stmts().iter().map(|stmt| stmt.insn.encode()).collect();
+    assert_eq!(words, vec![
+        0x1970, 0x2808, // while_clr: btfsc, goto 8
+        0x1D70, 0x2805, // if_set: btfss, goto 5
+        0x2807, // else: goto 7
+        0x0BF0, 0x33FE, // endl: decfsz, bra 5
+        0x33F8, // endw: bra 0
+    ]);
+    let labels: Vec<_> = tr_unit.stmts().iter()
+        .flat_map(|stmt| {
+            stmt.labels.iter().map(move |&(label, _)| (label, stmt.addr))
+        })
+        .collect();
+    assert_eq!(labels, vec![
+        ("_while1", 0), ("_if2_else", 5), ("_loop3", 5), ("_if2_end", 7),
+    ]);
+    assert_eq!(tr_unit.constants.values["_while1_end"], 8);
+    assert_eq!(::lint(&tr_unit), Vec::<String>::new());
+
+    for (src, msg) in &[
+        ("    endif\n", "line 1: endif without a block to close"),
+        (
+            "    if_clr 0x70, 1\n    endw\n",
+            "line 2: endw doesn't match if_clr on line 1",
+        ),
+        ("    while_set 0x70, 1\n", "line 1: while_set is never closed"),
+        (
+            "    if_set 0x70, 1\n    org 0x800\n    endif\n",
+            "line 3: the goto at 0x1 can't reach _if1_end at 0x800, on \
+                another 2K page",
+        ),
+        (
+            "    org 0x7FF\n    loop_decfsz 0x70\n    org 0x900\n    endl\n",
+            "line 4: the goto at 0x901 can't reach _loop1 at 0x7FF, on \
+                another 2K page",
+        ),
+    ] {
+        assert_eq!(::build_tr_unit(src, "test.asm").unwrap_err(), *msg);
+    }
+}
@@ -0,0 +1,150 @@
+//! Data memory allocation. Variables are declared with `res` in named
+//! `udata` (banked GPR) or `udata_shr` (common RAM) sections, and each
+//! section is placed whole in a single bank so one `banksel` covers it.
+//! A `cblock` ... `endc` block is a nameless banked section, pinned to an
+//! address if one is given.
+
+use device::Device;
+use Span;
+
+#[derive(Debug)]
+pub(crate) struct Var<'s> {
+    pub(crate) name: &'s str,
+    pub(crate) size: u16,
+    /// Offset from the start of the section.
+    pub(crate) offset: u16,
+    pub(crate) span: Span,
+}
+
+#[derive(Debug)]
+pub(crate) struct DataSection<'s> {
+    pub(crate) name: &'s str,
+    pub(crate) shared: bool,
+    pub(crate) vars: Vec<Var<'s>>,
+    /// Banked address of the first byte: set up front for a pinned
+    /// `cblock`, and by `alloc` for the rest.
+    pub(crate) addr: Option<u16>,
+    /// Opened by `cblock`, so `name` is just the directive
+    pub(crate) cblock: bool,
+    pub(crate) span: Span,
+}
+
+impl<'s> DataSection<'s> {
+    pub(crate) fn new(name: &'s str, shared: bool, span: Span) -> Self {
+        DataSection {
+            name, shared, vars: vec![], addr: None, cblock: false, span,
+        }
+    }
+
+    pub(crate) fn size(&self) -> u16 {
+        self.vars.iter().map(|var| var.size).sum()
+    }
+
+    pub(crate) fn push(&mut self, name: &'s str, size: u16, span: Span) {
+        let offset = self.size();
+        self.vars.push(Var { name, size, offset, span });
+    }
+}
+
+/// Takes `start..end` out of whichever free region holds all of it.
+fn carve(regions: &mut Vec<(u16, u16)>, start: u16, end: u16) -> bool {
+    let i = match regions.iter()
+        .position(|&(next, last)| next <= start && end <= last)
+    {
+        Some(i) => i,
+        None => return false,
+    };
+    let (next, last) = regions[i];
+    regions[i] = (end, last);
+    regions.insert(i, (next, start));
+    true
+}
+
+/// Reserves the pinned sections, then places the rest first-fit in
+/// declaration order.
+pub(crate) fn alloc(dev: &Device, sections: &mut [DataSection])
+    -> Result<(), String>
+{
+    // (next free address, end) for each region
+    let mut gpr = dev.gpr.to_vec();
+    let mut common = vec![dev.common];
+
+    for sec in sections.iter().filter(|sec| sec.addr.is_some()) {
+        let start = sec.addr.unwrap();
+        let end = start + sec.size();
+        if !carve(&mut gpr, start, end) && !carve(&mut common, start, end) {
+            return Err(format!(
+                "line {}: {} bytes at 0x{:03X} aren't free RAM in {}",
+                sec.span.line,
+                sec.size(),
+                start,
+                dev.name,
+            ));
+        }
+    }
+
+    for sec in sections.iter_mut().filter(|sec| sec.addr.is_none()) {
+        let size = sec.size();
+        let regions: &mut [(u16, u16)] =
+            if sec.shared { &mut common } else { &mut gpr };
+        let region = regions.iter_mut()
+            .find(|&&mut (next, end)| end - next >= size)
+            .ok_or_else(|| format!(
+                "no room for data section '{}' ({} bytes) in {} {}",
+                sec.name,
+                size,
+                dev.name,
+                if sec.shared { "common RAM" } else { "GPR banks" },
+            ))?;
+        sec.addr = Some(region.0);
+        region.0 += size;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[test]
+fn test_alloc() {
+    let dev = Device::find("PIC16F1829").unwrap();
+    let span = Span { line: 1, col: 1 };
+
+    let mut big = DataSection::new("big", false, span);
+    big.push("buf", 64, span);
+    let mut small = DataSection::new("small", false, span);
+    small.push("a", 1, span);
+    small.push("b", 2, span);
+    let mut big2 = DataSection::new("big2", false, span);
+    big2.push("buf2", 32, span);
+    let mut shr = DataSection::new("shr", true, span);
+    shr.push("w_save", 1, span);
+    let mut sections = vec![big, small, big2, shr];
+
+    alloc(dev, &mut sections).unwrap();
+    assert_eq!(sections[0].addr, Some(0x020));
+    assert_eq!(sections[1].addr, Some(0x060));
+    assert_eq!(sections[1].vars[1].offset, 1);
+    assert_eq!(sections[2].addr, Some(0x0A0)); // doesn't fit in bank 0
+    assert_eq!(sections[3].addr, Some(0x070));
+
+    let mut pinned = DataSection::new("cblock", false, span);
+    pinned.push("flags", 2, span);
+    pinned.addr = Some(0x021);
+    let mut after = DataSection::new("after", false, span);
+    after.push("x", 1, span);
+    let mut sections = vec![after, pinned];
+    alloc(dev, &mut sections).unwrap();
+    assert_eq!(sections[0].addr, Some(0x020));
+    assert_eq!(sections[1].addr, Some(0x021));
+
+    let mut overlap = DataSection::new("cblock", false, span);
+    overlap.push("w_save", 1, span);
+    overlap.addr = Some(0x022);
+    let mut pinned = DataSection::new("cblock", false, span);
+    pinned.push("flags", 2, span);
+    pinned.addr = Some(0x021);
+    assert!(alloc(dev, &mut [pinned, overlap]).is_err());
+
+    let mut too_big = DataSection::new("too_big", false, span);
+    too_big.push("huge", 81, span);
+    assert!(alloc(dev, &mut [too_big]).is_err());
+}
@@ -0,0 +1,199 @@
+//! Worst-case hardware stack depth. We follow the control flow graph (see
+//! `flow`) from each root to find everything a function can call, then
+//! take the deepest chain of calls.
+//!
+//! The roots are the vectors: 0x0000 and, if it has a handler, 0x0004. An
+//! interrupt can come in at the deepest point of the main program and
+//! takes a level of its own, so the worst case is the two depths plus one.
+//!
+//! `callw` and `brw` go wherever W says, so they need a `targets`
+//! annotation. Without one, `callw` calls nothing we know of (and `lint`
+//! says so).
+
+use flow::{Cfg, RESET_VECTOR};
+use std::collections::BTreeMap;
+use TrUnit;
+
+/// Every enhanced midrange device has a 16-level return stack.
+pub(crate) const STACK_LEVELS: usize = 16;
+
+pub(crate) struct StackReport {
+    /// (root name, depth, entry point of each function in the deepest
+    /// chain of calls, starting with the root)
+    pub(crate) roots: Vec<(&'static str, usize, Vec<u16>)>,
+    /// Worst case with an interrupt on top of the main program
+    pub(crate) combined: usize,
+}
+
+struct Analysis<'a, 's: 'a> {
+    tr_unit: &'a TrUnit<'s>,
+    cfg: Cfg,
+    /// Deepest chain of calls from each function, once known
+    chains: BTreeMap<u16, Vec<u16>>,
+    /// Functions being analyzed, to catch recursion
+    active: Vec<u16>,
+}
+
+impl<'a, 's> Analysis<'a, 's> {
+    /// The functions called from anywhere reachable from `entry` without
+    /// returning.
+    fn callees(&self, entry: u16) -> Vec<u16> {
+        let mut seen = vec![false; self.cfg.next.len()];
+        let mut todo = vec![entry];
+        let mut callees = vec![];
+        while let Some(addr) = todo.pop() {
+            let i = addr as usize;
+            if i >= seen.len() || seen[i] {
+                continue;
+            }
+            seen[i] = true;
+            todo.extend(&self.cfg.next[i]);
+            callees.extend(&self.cfg.calls[i]);
+        }
+        callees.sort();
+        callees.dedup();
+        callees
+    }
+
+    /// The deepest chain of calls from `entry`, starting with `entry`.
+    fn chain(&mut self, entry: u16) -> Result<Vec<u16>, String> {
+        if let Some(chain) = self.chains.get(&entry) {
+            return Ok(chain.clone());
+        }
+        if let Some(i) = self.active.iter().position(|&a| a == entry) {
+            let mut cycle = self.active[i..].to_vec();
+            cycle.push(entry);
+            return Err(format!(
+                "recursive call, so the stack depth has no limit: {}",
+                path(self.tr_unit, &cycle),
+            ));
+        }
+        self.active.push(entry);
+        let mut deepest = vec![];
+        for callee in self.callees(entry) {
+            let chain = self.chain(callee)?;
+            if chain.len() > deepest.len() {
+                deepest = chain;
+            }
+        }
+        self.active.pop();
+        deepest.insert(0, entry);
+        self.chains.insert(entry, deepest.clone());
+        Ok(deepest)
+    }
+}
+
+/// `a -> b -> c`, by label where there is one.
+fn path(tr_unit: &TrUnit, chain: &[u16]) -> String {
+    let names: Vec<_> = chain.iter()
+        .map(|&addr| tr_unit.addr_name(addr))
+        .collect();
+    names.join(" -> ")
+}
+
+/// Finds the deepest chain of calls from each root. Fails only on
+/// recursion.
+pub(crate) fn analyze(tr_unit: &TrUnit) -> Result<StackReport, String> {
+    let mut analysis = Analysis {
+        tr_unit,
+        cfg: Cfg::new(tr_unit),
+        chains: BTreeMap::new(),
+        active: vec![],
+    };
+    let mut roots = vec![];
+    let vectors = analysis.cfg.vectors.clone();
+    for vector in vectors {
+        if vector as usize >= tr_unit.stmts().len() {
+            continue;
+        }
+        let chain = analysis.chain(vector)?;
+        let root = if vector == RESET_VECTOR { "reset" } else { "interrupt" };
+        roots.push((root, chain.len() - 1, chain));
+    }
+    let combined = match roots[..] {
+        [(_, main, _), (_, isr, _)] => main + 1 + isr,
+        [(_, main, _)] => main,
+        _ => 0,
+    };
+    Ok(StackReport { roots, combined })
+}
+
+impl StackReport {
+    /// One line per root, plus the combined depth if there's an interrupt
+    /// handler.
+    pub(crate) fn lines(&self, tr_unit: &TrUnit) -> Vec<String> {
+        let mut lines: Vec<_> = self.roots.iter()
+            .map(|&(root, depth, ref chain)| format!(
+                "Stack depth from {}: {} ({})",
+                root, depth, path(tr_unit, chain),
+            ))
+            .collect();
+        if self.roots.len() > 1 {
+            lines.push(format!(
+                "Stack depth with interrupt: {}", self.combined,
+            ));
+        }
+        lines
+    }
+
+    /// Fails if the worst case is more than the hardware has.
+    pub(crate) fn check(&self, tr_unit: &TrUnit) -> Result<(), String> {
+        if self.combined <= STACK_LEVELS {
+            return Ok(());
+        }
+        Err(format!(
+            "worst-case stack depth is {} but there are only {} levels\n{}",
+            self.combined,
+            STACK_LEVELS,
+            self.lines(tr_unit).join("\n"),
+        ))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_analyze() {
+    let src = |targets| format!("\
+    goto main
+    nop
+    nop
+    nop
+isr:
+    call c
+    retfie
+main:
+    call a
+    bra main
+a:
+    call b
+    return
+b:
+    {}
+    callw
+    return
+c:
+    return
+", targets);
+
+    let src_without = src("");
+    let tr_unit = ::build_tr_unit(&src_without, "test.asm").unwrap();
+    let report = analyze(&tr_unit).unwrap();
+    assert_eq!(report.roots[0], ("reset", 2, vec![0, 8, 10]));
+    assert_eq!(report.roots[1], ("interrupt", 1, vec![4, 12]));
+    assert_eq!(report.combined, 4);
+
+    let src_with = src("targets c");
+    let tr_unit = ::build_tr_unit(&src_with, "test.asm").unwrap();
+    let report = analyze(&tr_unit).unwrap();
+    assert_eq!(report.combined, 5);
+    assert_eq!(report.lines(&tr_unit), vec![
+        "Stack depth from reset: 3 (0x0000 -> a -> b -> c)",
+        "Stack depth from interrupt: 1 (isr -> c)",
+        "Stack depth with interrupt: 5",
+    ]);
+    assert!(report.check(&tr_unit).is_ok());
+
+    let src_loop = src("targets a");
+    assert!(::build_tr_unit(&src_loop, "test.asm").unwrap_err()
+        .contains("a -> b -> a"));
+}